@@ -0,0 +1,264 @@
+use anyhow::{anyhow, bail};
+
+/// Header byte of item chat links, other link types (skins, maps, ...) use other headers
+const ITEM_HEADER: u8 = 0x02;
+
+const SKIN_FLAG: u8 = 0x80;
+const FIRST_UPGRADE_FLAG: u8 = 0x40;
+const SECOND_UPGRADE_FLAG: u8 = 0x20;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Content of an item chat link like `[&AgEAWgAA]`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemLink {
+    pub id: usize,
+    pub count: usize,
+    pub skin: Option<usize>,
+    /// Up to two upgrade ids, e.g. sigils or runes
+    pub upgrades: Vec<usize>,
+}
+
+impl ItemLink {
+    /// Whether the given text looks like a chat link, it might still be invalid
+    pub fn is_link(text: &str) -> bool {
+        text.starts_with("[&") && text.ends_with(']')
+    }
+
+    /// Decodes a chat link of the form `[&<base64>]`
+    pub fn decode(link: &str) -> anyhow::Result<Self> {
+        let Some(code) = link
+            .trim()
+            .strip_prefix("[&")
+            .and_then(|c| c.strip_suffix(']'))
+        else {
+            bail!("Chat link has to look like [&...]");
+        };
+
+        let bytes = base64_decode(code).ok_or(anyhow!("Invalid chat link '{link}'"))?;
+        let mut reader = bytes.iter().copied();
+
+        if reader.next() != Some(ITEM_HEADER) {
+            bail!("Chat link '{link}' is not an item link");
+        }
+
+        let truncated = || anyhow!("Chat link '{link}' is too short");
+        let count = reader.next().ok_or_else(truncated)? as usize;
+        let (id, flags) = read_id(&mut reader).ok_or_else(truncated)?;
+
+        let mut item = Self {
+            id,
+            count,
+            skin: None,
+            upgrades: vec![],
+        };
+
+        if flags & SKIN_FLAG != 0 {
+            item.skin = Some(read_id(&mut reader).ok_or_else(truncated)?.0);
+        }
+        for flag in [FIRST_UPGRADE_FLAG, SECOND_UPGRADE_FLAG] {
+            if flags & flag != 0 {
+                item.upgrades
+                    .push(read_id(&mut reader).ok_or_else(truncated)?.0);
+            }
+        }
+
+        Ok(item)
+    }
+
+    /// Encodes this item as `[&<base64>]`, only the first two upgrades fit into a link
+    pub fn encode(&self) -> String {
+        let mut flags = 0;
+        if self.skin.is_some() {
+            flags |= SKIN_FLAG;
+        }
+        for (flag, _) in [FIRST_UPGRADE_FLAG, SECOND_UPGRADE_FLAG]
+            .iter()
+            .zip(&self.upgrades)
+        {
+            flags |= flag;
+        }
+
+        let mut bytes = vec![ITEM_HEADER, self.count.clamp(1, u8::MAX as usize) as u8];
+        write_id(&mut bytes, self.id, flags);
+        if let Some(skin) = self.skin {
+            write_id(&mut bytes, skin, 0);
+        }
+        for upgrade in self.upgrades.iter().take(2) {
+            write_id(&mut bytes, *upgrade, 0);
+        }
+
+        format!("[&{}]", base64_encode(&bytes))
+    }
+}
+
+/// Reads a 3 byte little endian id followed by one byte of flags
+fn read_id(reader: &mut impl Iterator<Item = u8>) -> Option<(usize, u8)> {
+    let mut id = 0;
+    for shift in [0, 8, 16] {
+        id |= (reader.next()? as usize) << shift;
+    }
+
+    Some((id, reader.next()?))
+}
+
+fn write_id(bytes: &mut Vec<u8>, id: usize, flags: u8) {
+    bytes.extend_from_slice(&(id as u32).to_le_bytes()[..3]);
+    bytes.push(flags);
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - i * 8));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_CHARS[(buffer >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in input.bytes() {
+        let value = BASE64_CHARS.iter().position(|b| *b == c)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_plain_item() {
+        let item = ItemLink::decode("[&AgH1WQAA]").unwrap();
+
+        assert_eq!(item.id, 23029);
+        assert_eq!(item.count, 1);
+        assert_eq!(item.skin, None);
+        assert!(item.upgrades.is_empty());
+    }
+
+    #[test]
+    fn decodes_skin_and_two_upgrades() {
+        let item = ItemLink::decode("[&AgGqtgDgfQ4AAP9fAAAnYAAA]").unwrap();
+
+        assert_eq!(item.id, 46762);
+        assert_eq!(item.count, 1);
+        assert_eq!(item.skin, Some(3709));
+        assert_eq!(item.upgrades, vec![24575, 24615]);
+    }
+
+    #[test]
+    fn decodes_upgrades_without_skin() {
+        let item = ItemLink::decode("[&AgGqtgBg/18AACdgAAA=]").unwrap();
+
+        assert_eq!(item.skin, None);
+        assert_eq!(item.upgrades, vec![24575, 24615]);
+    }
+
+    #[test]
+    fn encodes_known_links() {
+        for link in [
+            "[&AgH1WQAA]",
+            "[&AgGqtgDgfQ4AAP9fAAAnYAAA]",
+            "[&AgGqtgBg/18AACdgAAA=]",
+            "[&AvoJTQAA]",
+        ] {
+            assert_eq!(ItemLink::decode(link).unwrap().encode(), link);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let items = [
+            ItemLink {
+                id: 19721,
+                count: 250,
+                skin: None,
+                upgrades: vec![],
+            },
+            ItemLink {
+                id: 0xFFFFFF,
+                count: 1,
+                skin: Some(7),
+                upgrades: vec![24575],
+            },
+            ItemLink {
+                id: 30684,
+                count: 1,
+                skin: Some(5000),
+                upgrades: vec![24615, 24554],
+            },
+        ];
+
+        for item in items {
+            assert_eq!(ItemLink::decode(&item.encode()).unwrap(), item);
+        }
+    }
+
+    #[test]
+    fn clamps_count_and_upgrades() {
+        let item = ItemLink {
+            id: 19721,
+            count: 0,
+            skin: None,
+            upgrades: vec![1, 2, 3],
+        };
+        let decoded = ItemLink::decode(&item.encode()).unwrap();
+
+        assert_eq!(decoded.count, 1);
+        assert_eq!(decoded.upgrades, vec![1, 2]);
+    }
+
+    #[test]
+    fn rejects_malformed_links() {
+        for link in [
+            "",
+            "AgH1WQAA",
+            "[&AgH1WQAA",
+            "[&]",
+            "[&!!!!]",
+            "[&Ag==]",
+            "[&AgH1]",
+            // Map link
+            "[&BDgAAAA=]",
+        ] {
+            assert!(ItemLink::decode(link).is_err(), "{link}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_links() {
+        let link = "[&AgGqtgDgfQ4AAP9fAAAnYAAA]";
+        let code = &link[2..link.len() - 1];
+
+        for end in 0..code.len() {
+            assert!(ItemLink::decode(&format!("[&{}]", &code[..end])).is_err());
+        }
+    }
+}
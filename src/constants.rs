@@ -5,3 +5,5 @@ pub const RARE_COLOR: [f32; 4] = [0.9882, 0.8157, 0.0431, 1.0];
 pub const EXOTIC_COLOR: [f32; 4] = [1.0, 0.6431, 0.0196, 1.0];
 pub const ASCENDED_COLOR: [f32; 4] = [0.9843, 0.2431, 0.5529, 1.0];
 pub const LEGENDARY_COLOR: [f32; 4] = [0.4510, 0.1216, 0.9490, 1.0];
+
+pub const HIGHLIGHT_COLOR: [f32; 4] = [0.0, 1.0, 1.0, 1.0];
@@ -0,0 +1,94 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use crate::diagnostics::{DiagnosticEvent, Diagnostics};
+use crate::entities::Gw2Item;
+use crate::index::character_watch::CharacterWatch;
+use crate::index::find_my_sht::ItemSearch;
+use crate::index::refresh_progress::RefreshProgress;
+use crate::index::synonyms::Synonyms;
+use crate::records::RecordStore;
+use crate::settings::settings::Settings;
+use crate::tantivy::SearchIndex;
+use crate::workers::{CancellationToken, WorkerPool};
+
+static CONTEXT: RwLock<Option<Arc<AppContext>>> = RwLock::new(None);
+
+/// All state shared between the render thread and worker threads.
+/// Created in `load` and dropped in `unload`
+pub struct AppContext {
+    pub settings: RwLock<Settings>,
+    pub search: Mutex<ItemSearch>,
+    pub diagnostics: Mutex<Diagnostics>,
+    /// Recent events shown in the diagnostics window, the oldest first
+    pub events: Mutex<VecDeque<DiagnosticEvent>>,
+    /// Opened lazily, see [crate::tantivy]
+    pub index: RwLock<Option<Arc<SearchIndex>>>,
+    /// Increased whenever a refresh was committed and is visible to searches
+    pub index_generation: AtomicU64,
+    /// Set if the index had to be thrown away on open
+    pub index_notice: Mutex<Option<String>>,
+    /// Items of the loaded account, see [RecordStore]
+    pub records: RwLock<Option<RecordStore>>,
+    /// Loaded lazily and again whenever the user file changed
+    pub synonyms: RwLock<Option<Arc<Synonyms>>>,
+    /// Set while a full or character refresh runs, refreshes never overlap
+    pub refreshing: AtomicBool,
+    /// Start of the last refresh, whether it succeeded or not
+    pub last_refresh_attempt: Mutex<Option<Instant>>,
+    /// Progress of the running or last refresh
+    pub refresh_progress: Mutex<Option<RefreshProgress>>,
+    pub character_watch: Mutex<CharacterWatch>,
+    /// Item details already fetched from the API, see [crate::utils::fetch_items]
+    pub item_cache: RwLock<HashMap<usize, Gw2Item>>,
+    /// Part of the texture ids of item icons, increased to load them again
+    pub icon_generation: AtomicU64,
+    pub workers: WorkerPool,
+    pub cancel: CancellationToken,
+}
+
+impl AppContext {
+    /// Nothing constructed here may call [ctx], the context is only available afterwards
+    pub fn create(settings: Settings) {
+        *CONTEXT.write().unwrap() = Some(Arc::new(Self {
+            settings: RwLock::new(settings),
+            search: Mutex::new(ItemSearch::new()),
+            diagnostics: Mutex::new(Diagnostics::new()),
+            events: Mutex::new(VecDeque::new()),
+            index: RwLock::new(None),
+            index_generation: AtomicU64::new(0),
+            index_notice: Mutex::new(None),
+            records: RwLock::new(None),
+            synonyms: RwLock::new(None),
+            refreshing: AtomicBool::new(false),
+            last_refresh_attempt: Mutex::new(None),
+            refresh_progress: Mutex::new(None),
+            character_watch: Mutex::new(CharacterWatch::default()),
+            item_cache: RwLock::new(HashMap::new()),
+            icon_generation: AtomicU64::new(0),
+            workers: WorkerPool::new(),
+            cancel: CancellationToken::default(),
+        }));
+    }
+
+    pub fn destroy() -> Option<Arc<AppContext>> {
+        CONTEXT.write().unwrap().take()
+    }
+}
+
+/// Context of the loaded addon.
+/// Don't keep a lock of it while calling code which might lock the same part again
+pub fn ctx() -> Arc<AppContext> {
+    try_ctx().expect("context to be created in load")
+}
+
+pub fn try_ctx() -> Option<Arc<AppContext>> {
+    CONTEXT.read().unwrap().clone()
+}
+
+/// Whether running work should stop, e.g. because the addon is unloaded
+pub fn cancelled() -> bool {
+    try_ctx().is_none_or(|ctx| ctx.cancel.is_cancelled())
+}
@@ -0,0 +1,253 @@
+use chrono::{DateTime, Local};
+use nexus::imgui::{Ui, Window};
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::context::{ctx, try_ctx};
+use crate::index::refresh_progress::refresh_progress;
+
+/// Older events are dropped
+const MAX_EVENTS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Request,
+    Refresh,
+    Index,
+    Search,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 4] = [
+        EventKind::Request,
+        EventKind::Refresh,
+        EventKind::Index,
+        EventKind::Search,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EventKind::Request => "Requests",
+            EventKind::Refresh => "Refresh",
+            EventKind::Index => "Index",
+            EventKind::Search => "Search",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticEvent {
+    pub at: DateTime<Local>,
+    pub kind: EventKind,
+    pub message: String,
+    pub error: bool,
+}
+
+impl DiagnosticEvent {
+    fn line(&self) -> String {
+        format!(
+            "{} [{}] {}",
+            self.at.format("%H:%M:%S%.3f"),
+            self.kind.label(),
+            self.message
+        )
+    }
+}
+
+fn record(kind: EventKind, message: String, error: bool) {
+    let Some(ctx) = try_ctx() else {
+        return;
+    };
+
+    let mut events = ctx.events.lock().unwrap();
+    if events.len() == MAX_EVENTS {
+        events.pop_front();
+    }
+    events.push_back(DiagnosticEvent {
+        at: Local::now(),
+        kind,
+        message,
+        error,
+    });
+}
+
+/// Id lists can get very long, the path is enough
+fn endpoint_path(endpoint: &str) -> &str {
+    endpoint.split('?').next().unwrap_or(endpoint)
+}
+
+/// An API request, `status` is [None] if it never got a response
+pub fn request(endpoint: &str, status: Option<u16>, latency: Duration, retries: u32) {
+    let status_text = status.map_or("no response".to_string(), |s| s.to_string());
+    record(
+        EventKind::Request,
+        format!(
+            "{} {status_text} in {latency:?}, {retries} retries",
+            endpoint_path(endpoint)
+        ),
+        !status.is_some_and(|s| (200..300).contains(&s)),
+    );
+}
+
+/// An API request that was given up on, e.g. because the addon is unloading
+pub fn request_cancelled(endpoint: &str, latency: Duration, retries: u32) {
+    record(
+        EventKind::Request,
+        format!(
+            "{} cancelled after {latency:?}, {retries} retries",
+            endpoint_path(endpoint)
+        ),
+        false,
+    );
+}
+
+pub fn refresh_step(step: &str, took: Duration) {
+    record(EventKind::Refresh, format!("{step} took {took:?}"), false);
+}
+
+pub fn refresh_finished(summary: String, failed: bool) {
+    record(EventKind::Refresh, summary, failed);
+}
+
+pub fn index_commit(documents: usize, took: Duration, error: Option<String>) {
+    let message = match &error {
+        None => format!("Committed {documents} documents in {took:?}"),
+        Some(e) => format!("Commit failed after {took:?}: {e}"),
+    };
+    record(EventKind::Index, message, error.is_some());
+}
+
+pub fn search(
+    provider: &str,
+    query: &str,
+    took: Duration,
+    hits: Option<usize>,
+    error: Option<String>,
+) {
+    let message = match &error {
+        None => format!(
+            "{provider} \"{query}\" in {took:?}, {} hits",
+            hits.map_or("?".to_string(), |h| h.to_string())
+        ),
+        Some(e) => format!("{provider} \"{query}\" failed after {took:?}: {e}"),
+    };
+    record(EventKind::Search, message, error.is_some());
+}
+
+/// Window listing the recent events
+pub struct Diagnostics {
+    pub show: bool,
+    kinds: [bool; EventKind::ALL.len()],
+    errors_only: bool,
+    filter: String,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self {
+            show: false,
+            kinds: [true; EventKind::ALL.len()],
+            errors_only: false,
+            filter: "".to_string(),
+        }
+    }
+
+    fn matches(&self, event: &DiagnosticEvent) -> bool {
+        let kind = EventKind::ALL.iter().position(|k| *k == event.kind);
+        kind.is_some_and(|i| self.kinds[i])
+            && (!self.errors_only || event.error)
+            && (self.filter.is_empty()
+                || event
+                    .message
+                    .to_lowercase()
+                    .contains(&self.filter.to_lowercase()))
+    }
+
+    /// Shown events plus some state, meant to be attached to bug reports
+    fn report(&self, events: &[String]) -> String {
+        let mut report = format!(
+            "Find my Sh*t {} diagnostics, {}\n",
+            env!("CARGO_PKG_VERSION"),
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        );
+
+        let jobs = ctx().workers.stats();
+        let _ = writeln!(
+            report,
+            "Jobs: {} queued, {} running, {} finished, {} panicked",
+            jobs.queued, jobs.running, jobs.finished, jobs.panicked
+        );
+        if let Some(progress) = refresh_progress() {
+            let _ = writeln!(
+                report,
+                "Last refresh: {:?} at {} after {:?}",
+                progress.outcome,
+                progress.step.label(),
+                progress.elapsed()
+            );
+        }
+
+        report.push('\n');
+        for line in events {
+            report.push_str(line);
+            report.push('\n');
+        }
+
+        report
+    }
+
+    pub fn render(&mut self, ui: &Ui) {
+        if !self.show {
+            return;
+        }
+
+        let mut show = self.show;
+        Window::new("Find my Sh*t Diagnostics")
+            .opened(&mut show)
+            .collapsible(false)
+            .always_auto_resize(true)
+            .build(ui, || {
+                for (i, kind) in EventKind::ALL.iter().enumerate() {
+                    ui.checkbox(kind.label(), &mut self.kinds[i]);
+                    ui.same_line();
+                }
+                ui.checkbox("Errors only", &mut self.errors_only);
+                ui.input_text("Filter", &mut self.filter).build();
+
+                let lines = ctx()
+                    .events
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|e| self.matches(e))
+                    .map(|e| (e.line(), e.error))
+                    .collect::<Vec<_>>();
+
+                if ui.button("Copy report") {
+                    let lines = lines.iter().map(|(l, _)| l.clone()).collect::<Vec<_>>();
+                    ui.set_clipboard_text(self.report(&lines));
+                }
+                ui.same_line();
+                if ui.button("Clear") {
+                    ctx().events.lock().unwrap().clear();
+                }
+                ui.same_line();
+                ui.text_disabled(format!("{} events", lines.len()));
+
+                ui.child_window("##Events")
+                    .size([700.0, 300.0])
+                    .border(true)
+                    .build(|| {
+                        // Newest first
+                        for (line, error) in lines.iter().rev() {
+                            if *error {
+                                ui.text_colored([1.0, 0.3, 0.3, 1.0], line);
+                            } else {
+                                ui.text(line);
+                            }
+                        }
+                    });
+            });
+        self.show = show;
+    }
+}
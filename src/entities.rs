@@ -35,6 +35,12 @@ pub struct Gw2ApiKey {
     pub permissions: Vec<Gw2Permission>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Gw2Account {
+    pub id: String,
+    pub name: String,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Gw2PlayerItem {
     pub id: usize,
@@ -83,7 +89,37 @@ pub enum Gw2ItemType {
     Unknown,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+impl Gw2ItemType {
+    pub const ALL: [Gw2ItemType; 20] = [
+        Gw2ItemType::Armor,
+        Gw2ItemType::Back,
+        Gw2ItemType::Bag,
+        Gw2ItemType::Consumable,
+        Gw2ItemType::Container,
+        Gw2ItemType::CraftingMaterial,
+        Gw2ItemType::Gathering,
+        Gw2ItemType::Gizmo,
+        Gw2ItemType::JadeTechModule,
+        Gw2ItemType::Key,
+        Gw2ItemType::MiniPet,
+        Gw2ItemType::PowerCore,
+        Gw2ItemType::Relic,
+        Gw2ItemType::Tool,
+        Gw2ItemType::Trait,
+        Gw2ItemType::Trinket,
+        Gw2ItemType::Trophy,
+        Gw2ItemType::UpgradeComponent,
+        Gw2ItemType::Weapon,
+        Gw2ItemType::Unknown,
+    ];
+
+    /// Lowercase name used for indexing and filtering
+    pub fn key(&self) -> String {
+        format!("{self:?}").to_lowercase()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub enum Gw2Rarity {
     Junk,
     #[default]
@@ -97,6 +133,27 @@ pub enum Gw2Rarity {
 }
 
 impl Gw2Rarity {
+    pub const ALL: [Gw2Rarity; 8] = [
+        Gw2Rarity::Junk,
+        Gw2Rarity::Basic,
+        Gw2Rarity::Fine,
+        Gw2Rarity::Masterwork,
+        Gw2Rarity::Rare,
+        Gw2Rarity::Exotic,
+        Gw2Rarity::Ascended,
+        Gw2Rarity::Legendary,
+    ];
+
+    /// Lowercase name used for indexing and filtering
+    pub fn key(&self) -> String {
+        format!("{self:?}").to_lowercase()
+    }
+
+    /// Position from junk to legendary, used for sorting
+    pub fn rank(&self) -> u64 {
+        Self::ALL.iter().position(|r| r == self).unwrap_or(0) as u64
+    }
+
     pub fn color(&self) -> [f32; 4] {
         match self {
             Gw2Rarity::Junk => JUNK_COLOR,
@@ -111,7 +168,7 @@ impl Gw2Rarity {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Gw2Item {
     pub id: usize,
     pub name: String,
@@ -1,17 +1,24 @@
+use crate::chat_link::ItemLink;
+use crate::context::{cancelled, ctx};
 use crate::entities::{Gw2Item, Gw2ItemType, Gw2PlayerItem, Gw2Rarity, Gw2Tp};
-use crate::settings::settings::Settings;
-use crate::tantivy::{tantivy_index, TantivySchema};
-use crate::ui_utils::{build_tp, render_description, render_location, Renderable};
-use crate::{
-    spawn_thread, BANK_ICON_ID, INV_ICON_ID, MAT_STORE_ID, SHARED_INV_ICON_ID, WIKI_ICON_ID,
+use crate::tantivy::{tantivy_index, TantivySchema, SUGGEST_SEPARATOR};
+use crate::ui_utils::{
+    build_tp, render_description, render_highlighted, render_highlighted_wrapped, render_location,
+    Renderable,
 };
+use crate::utils::REQUEST_TIMEOUT;
+use crate::workers::Priority;
+use crate::{spawn_job, BANK_ICON_ID, INV_ICON_ID, MAT_STORE_ID, SHARED_INV_ICON_ID, WIKI_ICON_ID};
 use nexus::imgui::{Image, Ui};
 use nexus::texture::get_texture;
 use serde::{Deserialize, Serialize};
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
+use tantivy::schema::Facet;
 use tantivy::{doc, TantivyDocument};
 
 /// Defines where a specific item lies on the account
@@ -24,6 +31,40 @@ pub enum Location {
     MaterialStorage,
 }
 
+impl Location {
+    /// Kind of location used for indexing and filtering, characters are not distinguished
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Location::Character(_) => "character",
+            Location::Bank => "bank",
+            Location::SharedInventory => "shared",
+            Location::MaterialStorage => "materials",
+        }
+    }
+
+    /// Display name of a location kind
+    pub fn kind_label(kind: &str) -> &'static str {
+        match kind {
+            "character" => "on characters",
+            "bank" => "in bank",
+            "shared" => "in shared inventory",
+            "materials" => "in material storage",
+            _ => "somewhere",
+        }
+    }
+
+    /// Resolves user input like `mat` or `inv` to a location kind
+    pub fn kind_from(input: &str) -> Option<&'static str> {
+        match input {
+            "char" | "character" | "characters" | "inv" | "inventory" => Some("character"),
+            "bank" => Some("bank"),
+            "shared" | "shared_inventory" => Some("shared"),
+            "mat" | "mats" | "material" | "materials" | "storage" => Some("materials"),
+            _ => None,
+        }
+    }
+}
+
 /// Contains specific information for an item at a certain location
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PlayerItemSpecifics {
@@ -31,6 +72,31 @@ pub struct PlayerItemSpecifics {
     pub charges: usize,
     pub upgrades: Vec<usize>,
     pub infusions: Vec<usize>,
+    /// Last, so items stored without it still decode
+    #[serde(default)]
+    pub skin: Option<usize>,
+    /// Stacks at this location differ in skin or upgrades, the fields above are the first one's
+    #[serde(default)]
+    pub mixed: bool,
+}
+
+impl PlayerItemSpecifics {
+    /// Whether both describe the same kind of stack, only their amounts may differ
+    fn same_stack(&self, other: &Self) -> bool {
+        self.skin == other.skin
+            && self.upgrades == other.upgrades
+            && self.infusions == other.infusions
+    }
+}
+
+/// Parts of name and description matching the last search
+#[derive(Debug, Clone, Default)]
+pub struct Highlights {
+    pub name: Vec<Range<usize>>,
+    /// Description without markup, only set if it matched
+    pub description: Option<(String, Vec<Range<usize>>)>,
+    /// Short part of the description around the matches, only set if the name did not match
+    pub fragment: Option<(String, Vec<Range<usize>>)>,
 }
 
 /// Find my sh*t specific player item which is stored and used for indexing
@@ -43,22 +109,70 @@ pub struct PlayerItem {
     pub item_type: Gw2ItemType,
     pub rarity: Gw2Rarity,
     pub locations: HashMap<Location, PlayerItemSpecifics>,
-    #[serde(skip)]
+    #[serde(default)]
     pub tp_info: Option<Gw2Tp>,
     #[serde(skip)]
+    pub highlights: Highlights,
+    #[serde(skip)]
     wikiable: Arc<RwLock<Option<bool>>>,
 }
 
 impl PlayerItem {
-    pub(crate) fn doc(&self) -> TantivyDocument {
+    /// Document for this item, `name_rank` is the position of this item when sorted by name
+    pub(crate) fn doc(&self, name_rank: u64) -> TantivyDocument {
         let schema: TantivySchema = tantivy_index().schema().into();
+        let name = self.name.clone().to_lowercase();
 
-        doc!(
+        let mut doc = doc!(
             schema.id_field => self.id as u64,
-            schema.name_field => self.name.clone().to_lowercase(),
+            schema.name_field => name.clone(),
+            schema.name_keyword_field => name.clone(),
+            schema.name_words_field => name.clone(),
+            schema.suggest_field => format!("{name}{SUGGEST_SEPARATOR}{}", self.name),
             schema.descr_field => self.description.clone().unwrap_or("".to_string()).to_lowercase(),
-            schema.item_field => rmp_serde::to_vec(self).expect("to be serialized")
-        )
+            schema.rarity_field => self.rarity.key(),
+            schema.type_field => self.item_type.key(),
+            schema.count_field => self.total_count() as u64,
+            schema.name_rank_field => name_rank,
+            schema.rarity_rank_field => self.rarity.rank(),
+            schema.value_field => self.tp_value()
+        );
+
+        doc.add_facet(
+            schema.facet_field,
+            Facet::from_path(["rarity", &self.rarity.key()]),
+        );
+        doc.add_facet(
+            schema.facet_field,
+            Facet::from_path(["type", &self.item_type.key()]),
+        );
+
+        for location in self.locations.keys() {
+            doc.add_text(schema.location_field, location.kind());
+            doc.add_facet(
+                schema.facet_field,
+                Facet::from_path(["location", location.kind()]),
+            );
+            if let Location::Character(character) = location {
+                doc.add_text(schema.character_field, character.to_lowercase());
+            }
+        }
+
+        // Index every word start, so "ore" finds "mithril ore" by prefix as well
+        for tail in Self::word_tails(&name) {
+            doc.add_text(schema.name_prefix_field, tail);
+        }
+
+        doc
+    }
+
+    /// All suffixes of the given name which start at a word boundary
+    fn word_tails(name: &str) -> Vec<&str> {
+        name.char_indices()
+            .filter(|(i, _)| *i == 0 || name[..*i].ends_with(' '))
+            .map(|(i, _)| &name[i..])
+            .filter(|tail| !tail.starts_with(' '))
+            .collect()
     }
 
     pub fn from(location: Location, item: &Gw2PlayerItem, gw2item: &Gw2Item) -> Self {
@@ -74,26 +188,84 @@ impl PlayerItem {
                 PlayerItemSpecifics {
                     count: item.count,
                     charges: item.charges.unwrap_or(0),
+                    skin: item.skin,
                     upgrades: item.upgrades.clone().unwrap_or(vec![]),
                     infusions: item.infusions.clone().unwrap_or(vec![]),
+                    mixed: false,
                 },
             )]),
             tp_info: None,
+            highlights: Highlights::default(),
             wikiable: Arc::new(RwLock::new(None)),
         }
     }
 
+    /// Chat link of this item. Skin and upgrades are only included if all stacks have the same,
+    /// a link must never combine parts of different stacks
+    pub fn chat_link(&self) -> String {
+        let mut stacks = self.locations.values();
+        let first = stacks.next();
+        let specifics =
+            first.filter(|first| !first.mixed && stacks.all(|s| !s.mixed && s.same_stack(first)));
+
+        ItemLink {
+            id: self.id,
+            // Owned items are spread over stacks and locations, none of them holds the total
+            count: 1,
+            skin: specifics.and_then(|s| s.skin),
+            upgrades: specifics
+                .map(|s| s.upgrades.iter().chain(&s.infusions).copied().collect())
+                .unwrap_or_default(),
+        }
+        .encode()
+    }
+
+    /// Item which is not on the account, e.g. looked up by id
+    pub fn unowned(gw2item: &Gw2Item) -> Self {
+        Self {
+            id: gw2item.id,
+            name: gw2item.name.clone(),
+            description: gw2item.description.clone(),
+            icon: gw2item.icon.clone(),
+            item_type: gw2item.item_type.clone(),
+            rarity: gw2item.rarity.clone(),
+            locations: HashMap::new(),
+            tp_info: None,
+            highlights: Highlights::default(),
+            wikiable: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Amount of this item across all locations
+    pub fn total_count(&self) -> usize {
+        self.locations.values().map(|s| s.count).sum()
+    }
+
+    /// Trading post sell value of all owned items, 0 if unknown
+    pub fn tp_value(&self) -> u64 {
+        self.tp_info
+            .map(|tp| (tp.sells.unit_price * self.total_count()) as u64)
+            .unwrap_or(0)
+    }
+
     pub fn set_tp(&mut self, tp: Option<Gw2Tp>) {
         self.tp_info = tp;
     }
 
+    /// Texture id of the icon, changes once the icon cache was cleared
+    pub fn icon_key(&self) -> String {
+        match ctx().icon_generation.load(Ordering::SeqCst) {
+            0 => self.name.clone(),
+            generation => format!("{}#{generation}", self.name),
+        }
+    }
+
     pub(crate) fn add(&mut self, item: &PlayerItem) {
         for (loc, spec) in &item.locations {
             if let Some(curr) = self.locations.get_mut(loc) {
                 curr.count += spec.count.clone();
                 curr.charges += spec.charges.clone();
-                curr.infusions.append(&mut spec.infusions.clone());
-                curr.upgrades.append(&mut spec.upgrades.clone());
+                curr.mixed = curr.mixed || spec.mixed || !curr.same_stack(spec);
             } else {
                 self.locations.insert(loc.clone(), spec.clone());
             }
@@ -110,9 +282,14 @@ impl PlayerItem {
             *self.wikiable.write().unwrap() = Some(false);
 
             let wikiable = self.wikiable.clone();
-            spawn_thread(move || match ureq::head(&url).call() {
-                Ok(_) => *wikiable.write().unwrap() = Some(true),
-                Err(_) => {}
+            spawn_job(Priority::Normal, move || {
+                if cancelled() {
+                    return;
+                }
+
+                if ureq::head(&url).timeout(REQUEST_TIMEOUT).call().is_ok() {
+                    *wikiable.write().unwrap() = Some(true);
+                }
             });
             return;
         } else if let Some(false) = wikiable {
@@ -145,18 +322,19 @@ impl Renderable for PlayerItem {
 
     fn render_self(&self, ui: &Ui, max_width: Option<f32>) {
         let mut hovered = false;
-        if let Some(icon) = get_texture(self.name.clone()) {
+        if let Some(icon) = get_texture(self.icon_key()) {
             Image::new(icon.id(), [20.0, 20.0]).build(ui);
             hovered = ui.is_item_hovered();
             ui.same_line();
         }
 
         ui.align_text_to_frame_padding();
-        if Settings::get().color_items {
-            ui.text_colored(self.rarity.color(), &self.name);
+        let color = if ctx().settings.read().unwrap().color_items {
+            Some(self.rarity.color())
         } else {
-            ui.text(&self.name);
-        }
+            None
+        };
+        ui.group(|| render_highlighted(ui, color, &self.name, &self.highlights.name));
 
         hovered = hovered || ui.is_item_hovered();
         if hovered && (self.description.is_some() || self.tp_info.is_some()) {
@@ -169,10 +347,16 @@ impl Renderable for PlayerItem {
                     build_tp(ui, "Sells:", tp_info.sells.units());
                 }
 
-                ui.push_text_wrap_pos_with_pos(f32::max(ui.current_column_width(), 300.0));
+                let wrap_width = f32::max(ui.current_column_width(), 300.0);
+                ui.push_text_wrap_pos_with_pos(wrap_width);
 
-                if let Some(description) = &self.description.clone() {
-                    render_description(ui, description);
+                // Highlighting replaces the colors of the markup
+                match (&self.description, &self.highlights.description) {
+                    (_, Some((plain, highlights))) => {
+                        render_highlighted_wrapped(ui, plain, highlights, wrap_width)
+                    }
+                    (Some(description), None) => render_description(ui, description),
+                    (None, None) => {}
                 }
             })
         }
@@ -183,6 +367,11 @@ impl Renderable for PlayerItem {
             ui.set_cursor_pos([max_width + 35.0, ui.cursor_pos()[1]]);
         }
 
+        if self.locations.is_empty() {
+            ui.text_disabled("Not owned");
+            ui.same_line();
+        }
+
         let mut specifics = self.locations.clone();
         render_location(
             specifics.borrow_mut(),
@@ -219,7 +408,20 @@ impl Renderable for PlayerItem {
             }
         }
 
+        if ui.small_button(format!("Link##{}", self.id)) {
+            ui.set_clipboard_text(self.chat_link());
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Copy chat link");
+        }
+
         self.render_wiki(ui);
         ui.new_line();
+
+        // Show why this item was found if only the description matched
+        if let Some((fragment, highlights)) = &self.highlights.fragment {
+            ui.set_cursor_pos([ui.cursor_pos()[0] + 25.0, ui.cursor_pos()[1]]);
+            render_highlighted(ui, Some([0.7, 0.7, 0.7, 1.0]), fragment, highlights);
+        }
     }
 }
@@ -0,0 +1,95 @@
+use log::debug;
+use nexus::event::{MumbleIdentityUpdate, MUMBLE_IDENTITY_UPDATED};
+use nexus::event_consume;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use crate::context::{ctx, try_ctx};
+use crate::index::item_loader::{is_refreshing, refresh_characters};
+use crate::spawn_job;
+use crate::workers::Priority;
+
+/// Waits for more changes before refreshing, e.g. quickly swapping through characters.
+/// The API also needs a moment to pick up the character data after a map change
+const REFRESH_DELAY: Duration = Duration::from_secs(30);
+
+/// Last identity and the characters waiting for a refresh, kept in the [crate::context::AppContext]
+#[derive(Debug, Default)]
+pub struct CharacterWatch {
+    /// Character and map of the last identity update
+    identity: Option<(String, u32)>,
+    characters: BTreeSet<String>,
+    due: Option<Instant>,
+}
+
+/// Watches character swaps and map changes through MumbleLink
+pub fn subscribe() {
+    MUMBLE_IDENTITY_UPDATED
+        .subscribe(event_consume!(<MumbleIdentityUpdate> |identity| {
+            if let Some(identity) = identity {
+                on_identity(identity.name(), identity.map_id);
+            }
+        }))
+        .revert_on_unload();
+}
+
+fn on_identity(name: &str, map_id: u32) {
+    if name.is_empty() {
+        return;
+    }
+
+    // Updates might still arrive while unloading
+    let Some(ctx) = try_ctx() else {
+        return;
+    };
+
+    let mut watch = ctx.character_watch.lock().unwrap();
+    match watch.identity.clone() {
+        // The whole account is refreshed on load anyways
+        None => {}
+        // The character just left has the changes
+        Some((last_name, _)) if last_name != name => watch.queue(last_name),
+        // Loot and crafting of the current character
+        Some((_, last_map)) if last_map != map_id => watch.queue(name.to_string()),
+        // Identity updates also happen for other changes, e.g. the UI size
+        _ => return,
+    }
+
+    watch.identity = Some((name.to_string(), map_id));
+}
+
+impl CharacterWatch {
+    fn queue(&mut self, character: String) {
+        debug!("Queued refresh of {character}");
+        self.characters.insert(character);
+        self.due = Some(Instant::now() + REFRESH_DELAY);
+    }
+}
+
+/// Refreshes the given character after [REFRESH_DELAY] without any further changes
+pub fn queue(character: String) {
+    ctx().character_watch.lock().unwrap().queue(character);
+}
+
+/// Starts the refresh of all queued characters once it is due
+pub fn refresh_characters_if_due() {
+    // Not now, the characters stay queued
+    if is_refreshing() {
+        return;
+    }
+
+    let characters = {
+        let ctx = ctx();
+        let mut pending = ctx.character_watch.lock().unwrap();
+        if pending.due.is_none_or(|due| Instant::now() < due) {
+            return;
+        }
+
+        pending.due = None;
+        std::mem::take(&mut pending.characters)
+    };
+
+    spawn_job(Priority::Background, move || {
+        refresh_characters(characters.into_iter().collect())
+    });
+}
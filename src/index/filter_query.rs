@@ -0,0 +1,267 @@
+use anyhow::{anyhow, bail};
+use std::ops::Bound;
+use tantivy::query::{Query, RangeQuery, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption};
+use tantivy::Term;
+
+use crate::chat_link::ItemLink;
+use crate::entities::{Gw2ItemType, Gw2Rarity};
+use crate::fms_entities::player_item::Location;
+use crate::tantivy::TantivySchema;
+
+/// Comparison used by numeric filters, e.g. `count:>100`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    /// Splits e.g. `>=100` into the comparison and the number, no prefix means equal
+    fn split(value: &str) -> (Self, &str) {
+        if let Some(n) = value.strip_prefix(">=") {
+            (Comparison::GreaterOrEqual, n)
+        } else if let Some(n) = value.strip_prefix("<=") {
+            (Comparison::LessOrEqual, n)
+        } else if let Some(n) = value.strip_prefix('>') {
+            (Comparison::Greater, n)
+        } else if let Some(n) = value.strip_prefix('<') {
+            (Comparison::Less, n)
+        } else if let Some(n) = value.strip_prefix('=') {
+            (Comparison::Equal, n)
+        } else {
+            (Comparison::Equal, value)
+        }
+    }
+
+    /// Range query on a fast u64 field
+    fn query(&self, field: &str, number: u64) -> Box<dyn Query> {
+        let (lower, upper) = match self {
+            Comparison::Less => (Bound::Unbounded, Bound::Excluded(number)),
+            Comparison::LessOrEqual => (Bound::Unbounded, Bound::Included(number)),
+            Comparison::Equal => (Bound::Included(number), Bound::Included(number)),
+            Comparison::GreaterOrEqual => (Bound::Included(number), Bound::Unbounded),
+            Comparison::Greater => (Bound::Excluded(number), Bound::Unbounded),
+        };
+
+        Box::new(RangeQuery::new_u64_bounds(field.to_string(), lower, upper))
+    }
+}
+
+/// A single structured filter like `rarity:exotic`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Rarity(String),
+    Type(String),
+    Location(String),
+    Character(String),
+    Count(Comparison, u64),
+    /// Trading post value of all owned items in copper
+    Value(Comparison, u64),
+    /// Exact item id, from `id:12345` or a pasted chat link
+    Id(u64),
+}
+
+/// Search input split into free text and filters
+#[derive(Debug, Clone, Default)]
+pub struct FilterQuery {
+    pub text: String,
+    pub filters: Vec<Filter>,
+}
+
+impl FilterQuery {
+    /// Parses e.g. `mithril rarity:basic loc:bank char:"Mule One" count:>100 value:>=1g` or `[&AgEAWgAA]`
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut text = vec![];
+        let mut filters = vec![];
+
+        for token in Self::tokens(input)? {
+            // Chat links are case sensitive, everything else is lowercased
+            if ItemLink::is_link(&token) {
+                filters.push(Filter::Id(ItemLink::decode(&token)?.id as u64));
+                continue;
+            }
+
+            let token = token.to_lowercase();
+            match token.split_once(':') {
+                Some((key, value))
+                    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()) =>
+                {
+                    filters.push(Self::filter(key, value.trim_matches('"'))?)
+                }
+                _ => text.push(token),
+            }
+        }
+
+        Ok(Self {
+            text: text.join(" "),
+            filters,
+        })
+    }
+
+    pub fn has_text(&self) -> bool {
+        !self.text.trim().is_empty()
+    }
+
+    /// Item ids searched for directly
+    pub fn ids(&self) -> Vec<usize> {
+        self.filters
+            .iter()
+            .filter_map(|filter| match filter {
+                Filter::Id(id) => Some(*id as usize),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Tantivy queries which all have to match
+    pub fn filter_queries(&self, schema: &TantivySchema) -> Vec<Box<dyn Query>> {
+        self.filters
+            .iter()
+            .map(|filter| match filter {
+                Filter::Rarity(rarity) => Self::term(schema.rarity_field, rarity),
+                Filter::Type(item_type) => Self::term(schema.type_field, item_type),
+                Filter::Location(location) => Self::term(schema.location_field, location),
+                Filter::Character(character) => Self::term(schema.character_field, character),
+                Filter::Count(comparison, count) => comparison.query("count", *count),
+                Filter::Value(comparison, copper) => comparison.query("value", *copper),
+                Filter::Id(id) => Box::new(TermQuery::new(
+                    Term::from_field_u64(schema.id_field, *id),
+                    IndexRecordOption::Basic,
+                )),
+            })
+            .collect()
+    }
+
+    fn term(field: Field, value: &str) -> Box<dyn Query> {
+        Box::new(TermQuery::new(
+            Term::from_field_text(field, value),
+            IndexRecordOption::Basic,
+        ))
+    }
+
+    /// Splits the input at whitespaces, keeping quoted parts together
+    fn tokens(input: &str) -> anyhow::Result<Vec<String>> {
+        let mut tokens = vec![];
+        let mut curr = String::new();
+        let mut quoted = false;
+
+        for c in input.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    curr.push(c);
+                }
+                c if c.is_whitespace() && !quoted => {
+                    if !curr.is_empty() {
+                        tokens.push(std::mem::take(&mut curr));
+                    }
+                }
+                c => curr.push(c),
+            }
+        }
+
+        if quoted {
+            bail!("Missing closing quote");
+        }
+
+        if !curr.is_empty() {
+            tokens.push(curr);
+        }
+
+        Ok(tokens)
+    }
+
+    fn filter(key: &str, value: &str) -> anyhow::Result<Filter> {
+        let value = value.to_lowercase();
+        if value.is_empty() {
+            bail!("Missing value for '{key}:'");
+        }
+
+        match key {
+            "rarity" => {
+                if Gw2Rarity::ALL.iter().any(|r| r.key() == value) {
+                    Ok(Filter::Rarity(value))
+                } else {
+                    Err(anyhow!("Unknown rarity '{value}'"))
+                }
+            }
+            "type" => {
+                let value = value.replace([' ', '_'], "");
+                if Gw2ItemType::ALL.iter().any(|t| t.key() == value) {
+                    Ok(Filter::Type(value))
+                } else {
+                    Err(anyhow!("Unknown item type '{value}'"))
+                }
+            }
+            "loc" | "location" => match Location::kind_from(&value) {
+                Some(kind) => Ok(Filter::Location(kind.to_string())),
+                None => Err(anyhow!(
+                    "Unknown location '{value}', use bank, materials, shared or character"
+                )),
+            },
+            "char" | "character" => Ok(Filter::Character(value)),
+            "count" => Self::count(&value),
+            "price" | "value" => Self::value(&value),
+            "id" => match value.parse::<u64>() {
+                Ok(id) => Ok(Filter::Id(id)),
+                Err(_) => Err(anyhow!("Invalid item id '{value}'")),
+            },
+            _ => Err(anyhow!("Unknown filter '{key}:'")),
+        }
+    }
+
+    fn count(value: &str) -> anyhow::Result<Filter> {
+        let (comparison, number) = Comparison::split(value);
+
+        match number.parse::<u64>() {
+            Ok(count) => Ok(Filter::Count(comparison, count)),
+            Err(_) => Err(anyhow!("Invalid count '{value}'")),
+        }
+    }
+
+    fn value(value: &str) -> anyhow::Result<Filter> {
+        let (comparison, amount) = Comparison::split(value);
+
+        match Self::copper(amount) {
+            Some(copper) => Ok(Filter::Value(comparison, copper)),
+            None => Err(anyhow!(
+                "Invalid value '{value}', use e.g. 2500, 25s or 1g50s"
+            )),
+        }
+    }
+
+    /// Parses plain copper or coins like `1g50s20c`
+    fn copper(amount: &str) -> Option<u64> {
+        if let Ok(copper) = amount.parse::<u64>() {
+            return Some(copper);
+        }
+
+        let mut copper = 0u64;
+        let mut number = String::new();
+        for c in amount.chars() {
+            let unit = match c {
+                '0'..='9' => {
+                    number.push(c);
+                    continue;
+                }
+                'g' => 10_000,
+                's' => 100,
+                'c' => 1,
+                _ => return None,
+            };
+            copper = copper.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+            number.clear();
+        }
+
+        // Trailing digits without a unit, e.g. `1g50`
+        if !number.is_empty() || amount.is_empty() {
+            return None;
+        }
+
+        Some(copper)
+    }
+}
@@ -1,58 +1,83 @@
-use crate::index::index_reader::IndexReader;
+use crate::context::ctx;
+use crate::fms_entities::player_item::PlayerItem;
+use crate::index::filter_query::FilterQuery;
+use crate::index::index_reader::{IndexReader, SortMode};
+use crate::index::item_loader::{is_refreshing, refresh_now};
+use crate::index::refresh_progress::{refresh_progress, RefreshOutcome};
+use crate::index::searcher::{SearchProvider, SearchSnapshot, Searcher};
 use crate::index::wiki_search::WikiReader;
-use crate::settings::settings::Settings;
+use crate::tantivy::{dismiss_index_notice, index_generation, index_notice};
 use crate::ui_utils::Renderable;
-use crate::utils::Searcher;
-use nexus::imgui::{Direction, Ui, Window};
-use std::borrow::{BorrowMut, Cow};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, OnceLock};
+use nexus::imgui::{Direction, Key, ProgressBar, Selectable, Ui, Window};
+use std::borrow::Cow;
 use std::time::{Duration, Instant};
 
 pub struct ItemSearch {
     pub show: bool,
     search: String,
-    page: Arc<AtomicUsize>,
     old_search: String,
     last_input_update: Instant,
-    acc_searcher: IndexReader,
-    wiki_searcher: WikiReader,
-    search_type: SearchType,
+    providers: Vec<Box<dyn SearchProvider>>,
+    mode: SearchMode,
+    force_search: bool,
+    suggestions: Vec<String>,
+    suggested_for: String,
+    selected_suggestion: Option<usize>,
+    /// Hides the completions until the input changes again
+    suggestion_accepted: bool,
+    input_active: bool,
+    suggestions_hovered: bool,
+    generation: u64,
+    updated_at: Option<Instant>,
 }
 
-#[derive(PartialEq, Eq)]
-pub enum SearchType {
-    Account,
-    Wiki,
+/// Max amount of name completions shown below the search box
+const MAX_SUGGESTIONS: usize = 8;
+
+/// How long the hint about refreshed results stays visible
+const UPDATED_HINT_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Every provider, each in its own section
+    All,
+    /// Index into the providers
+    Only(usize),
+}
+
+impl SearchMode {
+    fn includes(&self, provider: usize) -> bool {
+        match self {
+            SearchMode::All => true,
+            SearchMode::Only(i) => *i == provider,
+        }
+    }
+}
+
+/// All search providers, in the order their sections are shown
+fn providers() -> Vec<Box<dyn SearchProvider>> {
+    vec![Box::new(IndexReader::new()), Box::new(WikiReader::new())]
 }
 
-static mut SEARCH: OnceLock<ItemSearch> = OnceLock::new();
 impl ItemSearch {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             show: false,
-            page: Arc::new(AtomicUsize::new(0)),
             search: "".to_string(),
             old_search: "".to_string(),
             last_input_update: Instant::now(),
-            acc_searcher: IndexReader::new(),
-            wiki_searcher: WikiReader::new(),
-            search_type: SearchType::Account,
-        }
-    }
-
-    pub fn take() -> Option<Self> {
-        unsafe { SEARCH.take() }
-    }
-
-    pub fn get_mut() -> &'static mut Self {
-        unsafe {
-            if let Some(search) = SEARCH.get_mut() {
-                return search;
-            }
-
-            let _ = SEARCH.set(Self::new());
-            SEARCH.get_mut().expect("?")
+            providers: providers(),
+            mode: SearchMode::Only(0),
+            force_search: false,
+            suggestions: vec![],
+            suggested_for: "".to_string(),
+            selected_suggestion: None,
+            suggestion_accepted: false,
+            input_active: false,
+            suggestions_hovered: false,
+            // Created with the context, which starts at generation 0. It isn't available yet
+            generation: 0,
+            updated_at: None,
         }
     }
 
@@ -61,23 +86,37 @@ impl ItemSearch {
             return;
         }
 
+        let mut show = self.show;
         Window::new("Find my Sh*t")
-            .opened(&mut self.show)
+            .opened(&mut show)
             .collapsible(false)
             .resizable(false)
             .always_auto_resize(true)
             .focus_on_appearing(true)
             .build(ui, || {
+                // Accepting a suggestion with tab moves the focus away, take it back
+                if self.handle_suggestion_keys(ui) {
+                    ui.set_keyboard_focus_here();
+                }
+
                 if ui.input_text("", &mut self.search).build() {
                     self.last_input_update = Instant::now();
                 }
+                self.input_active = ui.is_item_active();
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "Filters: rarity:exotic type:weapon loc:bank char:\"Name\" count:>100 value:>=1g id:19721\n\
+                        Pasted chat links like [&AgEAWgAA] search for the linked item\n\
+                        Abbreviations like ecto or t6 blood are expanded, add your own in synonyms.json",
+                    );
+                }
 
                 if ui.is_window_appearing() {
                     ui.set_keyboard_focus_here();
                 }
 
                 ui.same_line();
-                if let Some(last_update) = Settings::get().last_update {
+                if let Some(last_update) = ctx().settings.read().unwrap().last_update {
                     ui.text(
                         last_update
                             .format(" Last Update: %b %d. %H:%M:%S")
@@ -86,116 +125,314 @@ impl ItemSearch {
                 } else {
                     ui.text(" Last Update: Unknown");
                 }
+                ui.same_line();
+                if is_refreshing() {
+                    ui.text_disabled("Refreshing...");
+                } else if ui.small_button("Refresh now") {
+                    refresh_now();
+                }
 
-                let items = ["Account", "Wiki"];
-
-                let mut current_index = match self.search_type {
-                    SearchType::Account => 0,
-                    SearchType::Wiki => 1,
-                };
+                if let Some(progress) = refresh_progress() {
+                    match progress.outcome {
+                        RefreshOutcome::Running => {
+                            ProgressBar::new(progress.fraction())
+                                .overlay_text(progress.describe())
+                                .build(ui);
+                        }
+                        RefreshOutcome::Failed => ui.text_colored(
+                            [1.0, 0.3, 0.3, 1.0],
+                            format!("Last refresh failed at {}", progress.step.label()),
+                        ),
+                        RefreshOutcome::PartiallyFailed => ui.text_colored(
+                            [1.0, 0.8, 0.2, 1.0],
+                            format!(
+                                "Last refresh could not fetch {} characters",
+                                progress.characters_failed
+                            ),
+                        ),
+                        _ => {}
+                    }
+                }
+                if self
+                    .updated_at
+                    .is_some_and(|at| at.elapsed() < UPDATED_HINT_DURATION)
+                {
+                    ui.same_line();
+                    ui.text_colored([0.3, 1.0, 0.3, 1.0], "Results updated");
+                }
 
-                ui.combo("##Type", &mut current_index, &items, |item| {
-                    Cow::from(item.to_string())
-                });
+                self.update_suggestions();
+                self.render_suggestions(ui);
 
-                let search_type = match current_index {
-                    0 => SearchType::Account,
-                    1 => SearchType::Wiki,
-                    _ => SearchType::Account,
-                };
+                if let Some(notice) = index_notice() {
+                    ui.text_colored([1.0, 0.8, 0.2, 1.0], notice);
+                    ui.same_line();
+                    if ui.small_button("Ok") {
+                        dismiss_index_notice();
+                    }
+                }
 
+                let mut modes = vec![SearchMode::All];
+                modes.extend((0..self.providers.len()).map(SearchMode::Only));
+                let mut mode_index = modes.iter().position(|m| *m == self.mode).unwrap_or(0);
                 let mut do_search = false;
-                if search_type != self.search_type {
-                    self.search_type = search_type;
+                if ui.combo("##Type", &mut mode_index, &modes, |mode| match mode {
+                    SearchMode::All => Cow::from("All"),
+                    SearchMode::Only(i) => Cow::from(self.providers[*i].name()),
+                }) {
+                    self.mode = modes[mode_index];
                     do_search = true;
                 }
 
+                // Options only apply to their own provider
+                for (i, provider) in self.providers.iter_mut().enumerate() {
+                    if self.mode.includes(i) && provider.render_options(ui) {
+                        provider.search(query_for(provider.as_ref(), &self.search), 0);
+                    }
+                }
+
                 if self.old_search != self.search
                     && self.last_input_update.elapsed() > Duration::from_millis(500)
                 {
                     do_search = true;
                 }
 
+                // A refresh finished, show the new data for the current query
+                let generation = index_generation();
+                if generation != self.generation {
+                    self.generation = generation;
+                    if !self.search.trim().is_empty() {
+                        for (i, provider) in self.providers.iter().enumerate() {
+                            if self.mode.includes(i) && provider.uses_index() {
+                                self.updated_at = Some(Instant::now());
+                                provider.search(self.search.clone(), 0);
+                            }
+                        }
+                    }
+                }
+
+                if self.force_search {
+                    self.force_search = false;
+                    do_search = true;
+                }
+
+                // Providers search in parallel, every one updates its own section
                 if do_search {
-                    self.page.store(0, Ordering::SeqCst);
-                    match self.search_type {
-                        SearchType::Account => self
-                            .acc_searcher
-                            .search(self.search.clone(), self.page.load(Ordering::SeqCst)),
-                        SearchType::Wiki => self
-                            .wiki_searcher
-                            .search(self.search.clone(), self.page.load(Ordering::SeqCst)),
+                    for (i, provider) in self.providers.iter().enumerate() {
+                        if self.mode.includes(i) {
+                            provider.search(query_for(provider.as_ref(), &self.search), 0);
+                        }
                     }
                     self.old_search = self.search.clone();
                 }
 
-                match self.search_type {
-                    SearchType::Account => {
-                        let acc_searcher = self.acc_searcher.borrow_mut();
-                        Self::render_search(
-                            Box::new(acc_searcher),
-                            ui,
-                            self.search.clone(),
-                            self.page.clone(),
-                            false,
-                        )
+                let mut clicked_filter = None;
+                for (i, provider) in self.providers.iter().enumerate() {
+                    if !self.mode.includes(i) {
+                        continue;
                     }
-                    SearchType::Wiki => {
-                        let wiki_searcher = self.wiki_searcher.borrow_mut();
-                        Self::render_search(
-                            Box::new(wiki_searcher),
-                            ui,
-                            self.search.clone(),
-                            self.page.clone(),
-                            true,
-                        )
+
+                    if self.mode == SearchMode::All {
+                        ui.separator();
+                        ui.text_disabled(provider.name());
                     }
+                    clicked_filter = provider.render_results(ui).or(clicked_filter);
+                }
+
+                if let Some(filter) = clicked_filter {
+                    self.search = format!("{} {filter}", self.search.trim())
+                        .trim()
+                        .to_string();
+                    self.force_search = true;
                 }
             });
+        self.show = show;
+    }
+
+    /// Looks up the name completions whenever the input changed, and picks up found ones
+    fn update_suggestions(&mut self) {
+        // Only plain names are completed, no filters or chat links
+        let plain = !self.search.contains([':', '"', '[']);
+        if self.search != self.suggested_for {
+            self.suggested_for = self.search.clone();
+            self.suggestion_accepted = false;
+            if plain {
+                for provider in self.included_providers() {
+                    provider.suggest(self.search.clone(), MAX_SUGGESTIONS);
+                }
+            }
+        }
+
+        let suggestions = if plain && !self.suggestion_accepted {
+            self.included_providers()
+                .flat_map(|provider| provider.suggestions(&self.search))
+                .take(MAX_SUGGESTIONS)
+                .collect()
+        } else {
+            vec![]
+        };
+        if suggestions != self.suggestions {
+            self.suggestions = suggestions;
+            self.selected_suggestion = None;
+        }
+    }
+
+    fn included_providers(&self) -> impl Iterator<Item = &dyn SearchProvider> {
+        self.providers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.mode.includes(*i))
+            .map(|(_, provider)| provider.as_ref())
+    }
+
+    /// Arrow keys move through the suggestions, tab accepts one. Returns true if accepted
+    fn handle_suggestion_keys(&mut self, ui: &Ui) -> bool {
+        if !self.input_active || self.suggestions.is_empty() {
+            return false;
+        }
+
+        let len = self.suggestions.len();
+        if ui.is_key_pressed(Key::DownArrow) {
+            self.selected_suggestion = Some(self.selected_suggestion.map_or(0, |i| (i + 1) % len));
+        }
+        if ui.is_key_pressed(Key::UpArrow) {
+            self.selected_suggestion = Some(
+                self.selected_suggestion
+                    .map_or(len - 1, |i| (i + len - 1) % len),
+            );
+        }
+
+        if ui.is_key_pressed(Key::Tab) {
+            self.accept_suggestion(self.selected_suggestion.unwrap_or(0));
+            return true;
+        }
+
+        false
+    }
+
+    fn accept_suggestion(&mut self, index: usize) {
+        if let Some(suggestion) = self.suggestions.get(index) {
+            self.search = suggestion.clone();
+            self.suggested_for = self.search.clone();
+            self.suggestion_accepted = true;
+            self.suggestions.clear();
+            self.selected_suggestion = None;
+            self.force_search = true;
+        }
+    }
+
+    fn render_suggestions(&mut self, ui: &Ui) {
+        let visible = (self.input_active || self.suggestions_hovered)
+            && !self.suggestions.is_empty()
+            && !(self.suggestions.len() == 1
+                && self.suggestions[0].eq_ignore_ascii_case(&self.search));
+        if !visible {
+            self.suggestions_hovered = false;
+            return;
+        }
+
+        let mut clicked = None;
+        ui.group(|| {
+            for (i, suggestion) in self.suggestions.iter().enumerate() {
+                if Selectable::new(suggestion)
+                    .selected(self.selected_suggestion == Some(i))
+                    .build(ui)
+                {
+                    clicked = Some(i);
+                }
+            }
+        });
+        self.suggestions_hovered = ui.is_item_hovered();
+
+        if let Some(i) = clicked {
+            self.accept_suggestion(i);
+        }
     }
 
-    fn render_search<T>(
-        searcher: Box<&dyn Searcher<Vec<T>>>,
+    /// `id` keeps the widgets of the sections apart
+    fn render_search<S: Searcher>(
+        searcher: &S,
+        snapshot: &SearchSnapshot<S::Item>,
         ui: &Ui,
-        query: String,
-        page: Arc<AtomicUsize>,
         hide_empty: bool,
-    ) where
-        T: Renderable,
-    {
-        let last_result = searcher.last_result();
-        if searcher.is_loading() && hide_empty {
-            for _ in 0..last_result.len() {
+        id: &str,
+    ) {
+        if let Some(error) = &snapshot.error {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
+        }
+
+        if let Some(corrected) = &snapshot.corrected {
+            ui.text_disabled(format!("Showing results for \"{corrected}\""));
+        }
+
+        if let Some(total_hits) = snapshot.total_hits.filter(|_| !snapshot.query.is_empty()) {
+            ui.text_disabled(format!("{total_hits} results"));
+        }
+
+        if snapshot.loading && hide_empty {
+            for _ in 0..snapshot.items.len() {
                 ui.text("");
             }
         } else {
-            let max_width = searcher
-                .last_result()
+            let max_width = snapshot
+                .items
                 .iter()
                 .map(|i| ui.calc_text_size(i.title())[0])
                 .reduce(f32::max);
 
-            for item in last_result.iter() {
+            for item in snapshot.items.iter() {
                 item.render_self(ui, max_width);
             }
         }
 
-        if !searcher.last_result().is_empty() {
-            Self::render_page_select(searcher, ui, query, page)
+        if !snapshot.items.is_empty() {
+            Self::render_page_select(searcher, snapshot, ui, id)
+        }
+    }
+
+    /// Renders the facet counts next to the results, returns the filter of a clicked facet
+    fn render_facets(ui: &Ui, snapshot: &SearchSnapshot<PlayerItem>) -> Option<String> {
+        let facets = &snapshot.facets;
+        if facets.is_empty() {
+            return None;
         }
+
+        let mut clicked = None;
+        ui.same_line();
+        ui.group(|| {
+            for group in facets.iter() {
+                ui.text_disabled(group.title);
+                for entry in &group.entries {
+                    // Already narrowed down to this one
+                    if snapshot.query.contains(&entry.filter) {
+                        ui.text(format!("{} {}", entry.count, entry.label));
+                        continue;
+                    }
+
+                    if ui.small_button(format!("{} {}##{}", entry.count, entry.label, entry.filter))
+                    {
+                        clicked = Some(entry.filter.clone());
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(format!("Narrow down with {}", entry.filter));
+                    }
+                }
+            }
+        });
+
+        clicked
     }
 
-    fn render_page_select<T>(
-        searcher: Box<&dyn Searcher<T>>,
+    /// Pages through the results of the query the snapshot belongs to
+    fn render_page_select<S: Searcher>(
+        searcher: &S,
+        snapshot: &SearchSnapshot<S::Item>,
         ui: &Ui,
-        query: String,
-        page: Arc<AtomicUsize>,
+        id: &str,
     ) {
-        let curr = page.load(Ordering::SeqCst);
-        if curr > 0 {
-            if ui.arrow_button("##Prev", Direction::Left) {
-                page.store(curr - 1, Ordering::SeqCst);
-                searcher.search(query.clone(), page.load(Ordering::SeqCst));
+        if snapshot.page > 0 {
+            if ui.arrow_button(format!("##Prev{id}"), Direction::Left) {
+                searcher.search(snapshot.query.clone(), snapshot.page - 1);
             }
         } else {
             ui.dummy([20.0, 20.0])
@@ -203,13 +440,83 @@ impl ItemSearch {
 
         ui.same_line();
 
-        if searcher.has_more() {
-            if ui.arrow_button("##More", Direction::Right) {
-                page.store(curr + 1, Ordering::SeqCst);
-                searcher.search(query.clone(), page.load(Ordering::SeqCst));
+        if snapshot.has_more {
+            if ui.arrow_button(format!("##More{id}"), Direction::Right) {
+                searcher.search(snapshot.query.clone(), snapshot.page + 1);
             }
         } else {
             ui.dummy([20.0, 20.0]);
         }
     }
 }
+
+/// Only the index understands filters, other providers get the free text of the query
+fn query_for(provider: &dyn SearchProvider, search: &str) -> String {
+    if provider.uses_index() {
+        return search.to_string();
+    }
+
+    FilterQuery::parse(search)
+        .map(|query| query.text)
+        .unwrap_or_default()
+}
+
+impl SearchProvider for IndexReader {
+    fn name(&self) -> &'static str {
+        "Account"
+    }
+
+    fn search(&self, query: String, page: usize) {
+        Searcher::search(self, query, page);
+    }
+
+    fn render_results(&self, ui: &Ui) -> Option<String> {
+        let snapshot = self.snapshot();
+        ui.group(|| ItemSearch::render_search(self, &snapshot, ui, false, self.name()));
+
+        ItemSearch::render_facets(ui, &snapshot)
+    }
+
+    fn render_options(&mut self, ui: &Ui) -> bool {
+        let mut sort_index = SortMode::ALL
+            .iter()
+            .position(|s| *s == self.sort())
+            .unwrap_or(0);
+        if ui.combo("Sort", &mut sort_index, &SortMode::ALL, |sort| {
+            Cow::from(sort.label())
+        }) {
+            self.set_sort(SortMode::ALL[sort_index]);
+            return true;
+        }
+
+        false
+    }
+
+    fn suggest(&self, text: String, limit: usize) {
+        IndexReader::suggest(self, text, limit);
+    }
+
+    fn suggestions(&self, text: &str) -> Vec<String> {
+        IndexReader::suggestions(self, text)
+    }
+
+    fn uses_index(&self) -> bool {
+        true
+    }
+}
+
+impl SearchProvider for WikiReader {
+    fn name(&self) -> &'static str {
+        "Wiki"
+    }
+
+    fn search(&self, query: String, page: usize) {
+        Searcher::search(self, query, page);
+    }
+
+    fn render_results(&self, ui: &Ui) -> Option<String> {
+        ItemSearch::render_search(self, &self.snapshot(), ui, true, self.name());
+
+        None
+    }
+}
@@ -1,77 +1,263 @@
 use log::error;
 use nexus::texture::{get_texture, load_texture_from_url};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::schema::Value;
-use tantivy::TantivyDocument;
-
-use crate::entities::Gw2Tp;
-use crate::fms_entities::player_item::PlayerItem;
-use crate::settings::settings::Settings;
-use crate::spawn_thread;
-use crate::tantivy::{index_searcher, tantivy_index, TantivySchema};
-use crate::utils::{auth_request, Searcher};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
+use tantivy::collector::{Count, FacetCollector, FacetCounts, TopDocs};
+use tantivy::postings::TermInfo;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, QueryParser, TermQuery,
+};
+use tantivy::schema::{Facet, Field, IndexRecordOption, Value};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::tokenizer::{NgramTokenizer, TextAnalyzer};
+use tantivy::{
+    DocSet, InvertedIndexReader, Order, Score, SegmentReader, TantivyDocument, Term, TERMINATED,
+};
+
+use crate::context::ctx;
+use crate::entities::{Gw2ItemType, Gw2Rarity, Gw2Tp};
+use crate::fms_entities::player_item::{Highlights, Location, PlayerItem};
+use crate::index::filter_query::FilterQuery;
+use crate::index::searcher::{record_search, SearchPage, SearchSnapshot, SearchState, Searcher};
+use crate::index::synonyms::Synonyms;
+use crate::records::RecordStore;
+use crate::spawn_job;
+use crate::tantivy::{
+    index_searcher, tantivy_index, TantivySchema, EDGE_NGRAM_MAX, SUGGEST_SEPARATOR,
+};
+use crate::ui_utils::strip_markup;
+use crate::utils::{auth_request, fetch_items, levenshtein};
+use crate::workers::Priority;
+
+/// Max length of the description part shown in the results if only the description matched
+const DESCRIPTION_FRAGMENT_CHARS: usize = 80;
+
+/// Below this amount of hits, a typo tolerant search is tried as well
+const FUZZY_MIN_HITS: usize = 3;
+
+/// Facet roots counted for every search, these equal the filter keys
+const FACET_GROUPS: [&str; 3] = ["rarity", "type", "location"];
+
+/// A single facet value with the amount of matching items
+#[derive(Clone)]
+pub struct FacetEntry {
+    pub label: String,
+    pub count: u64,
+    /// Filter narrowing the search down to this facet
+    pub filter: String,
+}
+
+/// Facet counts of one facet root, e.g. all rarities
+#[derive(Clone)]
+pub struct FacetGroup {
+    pub title: &'static str,
+    pub entries: Vec<FacetEntry>,
+}
+
+impl FacetGroup {
+    fn from_counts(group: &str, counts: &FacetCounts) -> Self {
+        let mut entries = counts
+            .get(Facet::from_path([group]))
+            .filter_map(|(facet, count)| {
+                let value = *facet.to_path().last()?;
+                let (label, filter) = match group {
+                    "rarity" => (
+                        Gw2Rarity::ALL
+                            .iter()
+                            .find(|r| r.key() == value)
+                            .map(|r| format!("{r:?}"))?,
+                        format!("rarity:{value}"),
+                    ),
+                    "type" => (
+                        Gw2ItemType::ALL
+                            .iter()
+                            .find(|t| t.key() == value)
+                            .map(|t| format!("{t:?}"))?,
+                        format!("type:{value}"),
+                    ),
+                    _ => (
+                        Location::kind_label(value).to_string(),
+                        format!("loc:{value}"),
+                    ),
+                };
+
+                Some(FacetEntry {
+                    label,
+                    count,
+                    filter,
+                })
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|e| Reverse(e.count));
+
+        Self {
+            title: match group {
+                "rarity" => "Rarity",
+                "type" => "Type",
+                _ => "Location",
+            },
+            entries,
+        }
+    }
+}
+
+/// Order of the search results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    Relevance,
+    Name,
+    Count,
+    Rarity,
+    Value,
+}
+
+impl SortMode {
+    pub const ALL: [SortMode; 5] = [
+        SortMode::Relevance,
+        SortMode::Name,
+        SortMode::Count,
+        SortMode::Rarity,
+        SortMode::Value,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortMode::Relevance => "Relevance",
+            SortMode::Name => "Name",
+            SortMode::Count => "Total Count",
+            SortMode::Rarity => "Rarity",
+            SortMode::Value => "TP Sell Value",
+        }
+    }
+
+    /// Fast field to sort by, [None] sorts by score
+    fn fast_field(&self) -> Option<(&'static str, Order)> {
+        match self {
+            SortMode::Relevance => None,
+            SortMode::Name => Some(("name_rank", Order::Asc)),
+            SortMode::Count => Some(("count", Order::Desc)),
+            SortMode::Rarity => Some(("rarity_rank", Order::Desc)),
+            SortMode::Value => Some(("value", Order::Desc)),
+        }
+    }
+}
 
 /// Contains all tantivy results from the last search
 pub struct IndexReader {
-    last_result: Arc<Mutex<Vec<PlayerItem>>>,
-    has_more: Arc<AtomicBool>,
-    loading: Arc<Mutex<bool>>,
+    sort: SortMode,
+    state: SearchState<PlayerItem>,
+    /// Name completions, computed in the background like searches
+    suggestions: SearchState<String>,
 }
 
 impl IndexReader {
     pub fn new() -> Self {
         Self {
-            last_result: Arc::new(Mutex::new(vec![])),
-            has_more: Arc::new(AtomicBool::new(false)),
-            loading: Arc::new(Mutex::new(false)),
+            sort: SortMode::Relevance,
+            state: SearchState::default(),
+            suggestions: SearchState::default(),
         }
     }
 
     fn search_for(
-        text: String,
+        text: &str,
         page: usize,
-        has_more: Arc<AtomicBool>,
-    ) -> anyhow::Result<Vec<PlayerItem>> {
+        sort: SortMode,
+    ) -> anyhow::Result<SearchPage<PlayerItem>> {
         let index = tantivy_index();
         let searcher = index_searcher();
 
         let schema: TantivySchema = index.schema().into();
-        let mut parser = QueryParser::for_index(index, vec![schema.name_field, schema.descr_field]);
-        // Put some more importance on names
-        parser.set_field_boost(schema.name_field, 2.0);
-        parser.set_conjunction_by_default();
+        let filter = FilterQuery::parse(text)?;
+        let filters = filter.filter_queries(&schema);
+        if !filter.has_text() && filters.is_empty() {
+            return Ok(SearchPage::default());
+        }
 
-        let query = parser.parse_query(&text)?;
-        let limit = Settings::get().item_load_limit as usize;
-        let top_docs = searcher.search(
-            &query,
-            &TopDocs::with_limit(limit + 1).and_offset(limit * page),
-        )?;
+        let expansion = Synonyms::get().expand(&filter.text);
+        let mut query = if filter.has_text() {
+            Self::with_filters(
+                Self::strict_query(&schema, &filter.text, &expansion.query)?,
+                &filters,
+            )
+        } else {
+            Self::with_filters(Box::new(AllQuery), &filters)
+        };
 
-        let mut found: Vec<PlayerItem> = vec![];
-        for (_, doc_address) in top_docs {
-            let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
-            if let Some(item) = retrieved_doc.get_first(schema.item_field) {
-                if let Ok(item) = rmp_serde::from_slice(item.as_bytes().unwrap()) {
-                    found.push(item);
+        // Nothing (or almost nothing) found, the user might have made a typo
+        let mut fuzzy = false;
+        let mut hits = searcher.search(&query, &Count)?;
+        if hits < FUZZY_MIN_HITS {
+            if let Some(fuzzy_query) = Self::fuzzy_query(&schema, &expansion.alternatives) {
+                let fuzzy_query = Self::with_filters(fuzzy_query, &filters);
+                let fuzzy_hits = searcher.search(&fuzzy_query, &Count)?;
+                if fuzzy_hits > hits {
+                    query = fuzzy_query;
+                    hits = fuzzy_hits;
+                    fuzzy = true;
                 }
             }
         }
 
-        has_more.store(found.len() > limit, Ordering::SeqCst);
+        let limit = ctx().settings.read().unwrap().item_load_limit as usize;
+        let mut facet_collector = FacetCollector::for_field("facets");
+        for group in FACET_GROUPS {
+            facet_collector.add_facet(Facet::from_path([group]));
+        }
+
+        let top_docs = TopDocs::with_limit(limit + 1).and_offset(limit * page);
+        let (doc_addresses, facet_counts) = match sort.fast_field() {
+            None => {
+                let (docs, facets) = searcher.search(&query, &(top_docs, facet_collector))?;
+                (docs.into_iter().map(|(_, d)| d).collect::<Vec<_>>(), facets)
+            }
+            Some((field, order)) => {
+                let (docs, facets) = searcher.search(
+                    &query,
+                    &(top_docs.order_by_u64_field(field, order), facet_collector),
+                )?;
+                (docs.into_iter().map(|(_, d)| d).collect::<Vec<_>>(), facets)
+            }
+        };
+        let facets = FACET_GROUPS
+            .iter()
+            .map(|group| FacetGroup::from_counts(group, &facet_counts))
+            .filter(|group| !group.entries.is_empty())
+            .collect();
+
+        // Records might be missing for some hits, more pages depend on the index only
+        let mut has_more = doc_addresses.len() > limit;
+
+        // The index only knows ids, the items themselves come from the record store
+        let mut ids = vec![];
+        for doc_address in doc_addresses.into_iter().take(limit) {
+            let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
+            if let Some(id) = retrieved_doc.get_first(schema.id_field) {
+                ids.extend(id.as_u64().map(|id| id as usize));
+            }
+        }
+        let mut found = RecordStore::items(&ids);
+
+        // Searched for an item we don't own, show what it is at least
+        if found.is_empty() && page == 0 && !filter.ids().is_empty() {
+            found = fetch_items(filter.ids())
+                .iter()
+                .map(PlayerItem::unowned)
+                .collect();
+            hits = found.len();
+            has_more = false;
+        }
         found.truncate(limit);
 
         // Load all icons for the search results
         for item in &found {
             if let Some(icon) = item.icon.clone() {
-                if get_texture(item.name.clone()).is_none() {
+                let key = item.icon_key();
+                if get_texture(&key).is_none() {
                     load_texture_from_url(
-                        item.name.clone(),
+                        key,
                         "https://render.guildwars2.com",
                         icon.strip_prefix("https://render.guildwars2.com").unwrap(),
                         None,
@@ -80,74 +266,389 @@ impl IndexReader {
             }
         }
 
-        Ok(found)
+        let correction = if fuzzy {
+            Self::correct(&filter.text, &found)
+        } else {
+            None
+        };
+        let words = correction
+            .clone()
+            .unwrap_or_else(|| expansion.words.join(" "));
+        Self::highlight(&searcher, query.as_ref(), &schema, &words, &mut found)?;
+
+        Ok(SearchPage {
+            items: found,
+            total_hits: Some(hits),
+            has_more,
+            corrected: correction,
+            facets,
+        })
     }
-}
 
-impl Searcher<Vec<PlayerItem>> for IndexReader {
-    fn is_loading(&self) -> bool {
-        self.loading.clone().lock().unwrap().clone()
+    pub fn sort(&self) -> SortMode {
+        self.sort
     }
 
-    fn has_more(&self) -> bool {
-        self.has_more.load(Ordering::SeqCst)
+    pub fn set_sort(&mut self, sort: SortMode) {
+        self.sort = sort;
     }
 
-    fn search(&self, text: String, page: usize) {
-        let last_result = self.last_result.clone();
-        let loading = self.loading.clone();
-        let has_more = self.has_more.clone();
-
-        spawn_thread(move || {
-            // Uuuuh no idea why I'm doing this, but never change a running system
-            while *loading.lock().unwrap() {
-                std::thread::sleep(Duration::from_millis(20));
-            }
+    /// Marks the query words in item names and the matched terms in descriptions
+    fn highlight(
+        searcher: &tantivy::Searcher,
+        query: &dyn Query,
+        schema: &TantivySchema,
+        text: &str,
+        found: &mut [PlayerItem],
+    ) -> anyhow::Result<()> {
+        // Names are matched by ngrams, so every substring equal to a query word is highlighted
+        let words = text
+            .split_whitespace()
+            .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()))
+            .filter(|w| !w.is_empty())
+            .map(|w| (w.to_string(), 1.0))
+            .collect::<BTreeMap<String, Score>>();
+        let name_generator = SnippetGenerator::new(
+            words,
+            TextAnalyzer::from(NgramTokenizer::all_ngrams(1, EDGE_NGRAM_MAX)?),
+            schema.name_field,
+            usize::MAX,
+        );
 
-            *loading.lock().unwrap() = true;
-            let result = Self::search_for(text.clone().to_lowercase(), page, has_more);
-            *loading.lock().unwrap() = false;
-            match result {
-                Ok(res) => {
-                    *last_result.lock().unwrap() = res.clone();
-
-                    let ids = res
-                        .iter()
-                        .map(|i| i.id.clone().to_string())
-                        .collect::<Vec<_>>()
-                        .join(",");
-
-                    let last_result = last_result.clone();
-                    spawn_thread(move || {
-                        match auth_request::<Vec<Gw2Tp>>(&format!("commerce/prices?ids={ids}")) {
-                            Ok(tp) => {
-                                let tp = tp
-                                    .iter()
-                                    .map(|tp| (tp.id, tp))
-                                    .collect::<HashMap<usize, &Gw2Tp>>();
-                                if tp.is_empty() {
-                                    return;
-                                }
+        let mut fragment_generator = SnippetGenerator::create(searcher, query, schema.descr_field)?;
+        fragment_generator.set_max_num_chars(DESCRIPTION_FRAGMENT_CHARS);
+        // A single fragment starting at 0, so the ranges apply to the whole description
+        let mut description_generator =
+            SnippetGenerator::create(searcher, query, schema.descr_field)?;
+        description_generator.set_max_num_chars(usize::MAX);
 
-                                let len = last_result.lock().unwrap().len();
-                                for i in 0..len {
-                                    let id = last_result.lock().unwrap()[i].id;
-                                    let tp = tp.get(&id).map(|x| **x);
-                                    last_result.lock().unwrap()[i].set_tp(tp);
-                                }
-                            }
-                            Err(e) => {
-                                error!("{e}");
-                            }
-                        }
-                    });
+        for item in found {
+            let name = item.name.to_lowercase();
+            let mut highlights = Highlights::default();
+            // Lowercasing may shift offsets for some special chars, don't highlight those
+            if name.len() == item.name.len() {
+                highlights.name = name_generator.snippet(&name).highlighted().to_vec();
+            }
+
+            if let Some(description) = item.description.as_deref().map(strip_markup) {
+                let matched = description_generator.snippet(&description);
+                if !matched.highlighted().is_empty() {
+                    let fragment = fragment_generator.snippet(&description);
+                    if highlights.name.is_empty() && !fragment.highlighted().is_empty() {
+                        highlights.fragment = Some((
+                            fragment.fragment().to_string(),
+                            fragment.highlighted().to_vec(),
+                        ));
+                    }
+                    highlights.description = Some((description, matched.highlighted().to_vec()));
                 }
-                Err(_) => {}
             }
+
+            item.highlights = highlights;
+        }
+
+        Ok(())
+    }
+
+    /// Starts looking up the item names starting with the given text
+    pub fn suggest(&self, text: String, limit: usize) {
+        self.suggestions.run(text, 0, move |text, _| {
+            Ok(SearchPage {
+                items: Self::suggest_for(text, limit)?,
+                ..SearchPage::default()
+            })
         });
     }
 
-    fn last_result(&self) -> Vec<PlayerItem> {
-        (*self.last_result.clone().lock().unwrap()).to_owned()
+    /// Last looked up names which still complete the given text
+    pub fn suggestions(&self, text: &str) -> Vec<String> {
+        let prefix = text.trim_start().to_lowercase();
+        if prefix.is_empty() {
+            return vec![];
+        }
+
+        self.suggestions
+            .snapshot()
+            .items
+            .iter()
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Item names starting with the given text, in alphabetical order
+    fn suggest_for(text: &str, limit: usize) -> anyhow::Result<Vec<String>> {
+        let prefix = text.trim_start().to_lowercase();
+        if prefix.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let searcher = index_searcher();
+        let schema: TantivySchema = tantivy_index().schema().into();
+
+        let mut found = BTreeSet::new();
+        for segment in searcher.segment_readers() {
+            let inverted_index = segment.inverted_index(schema.suggest_field)?;
+            let mut terms = inverted_index
+                .terms()
+                .range()
+                .ge(prefix.as_bytes())
+                .into_stream()?;
+
+            let mut taken = 0;
+            while taken < limit && terms.advance() {
+                let Ok(term) = std::str::from_utf8(terms.key()) else {
+                    continue;
+                };
+                if !term.starts_with(&prefix) {
+                    break;
+                }
+
+                if !Self::has_alive_doc(segment, &inverted_index, terms.value())? {
+                    continue;
+                }
+
+                if let Some((key, name)) = term.split_once(SUGGEST_SEPARATOR) {
+                    found.insert((key.to_string(), name.to_string()));
+                    taken += 1;
+                }
+            }
+        }
+
+        Ok(found
+            .into_iter()
+            .map(|(_, name)| name)
+            .take(limit)
+            .collect())
+    }
+
+    /// Deleted documents keep their terms until their segment is merged
+    fn has_alive_doc(
+        segment: &SegmentReader,
+        inverted_index: &InvertedIndexReader,
+        term_info: &TermInfo,
+    ) -> anyhow::Result<bool> {
+        let Some(alive) = segment.alive_bitset() else {
+            return Ok(true);
+        };
+
+        let mut postings =
+            inverted_index.read_postings_from_terminfo(term_info, IndexRecordOption::Basic)?;
+        while postings.doc() != TERMINATED {
+            if alive.is_alive(postings.doc()) {
+                return Ok(true);
+            }
+            postings.advance();
+        }
+
+        Ok(false)
+    }
+
+    /// Restricts the given query to documents matching all filters
+    fn with_filters(query: Box<dyn Query>, filters: &[Box<dyn Query>]) -> Box<dyn Query> {
+        if filters.is_empty() {
+            return query;
+        }
+
+        let mut clauses = vec![(Occur::Must, query)];
+        clauses.extend(filters.iter().map(|f| (Occur::Must, f.box_clone())));
+
+        Box::new(BooleanQuery::new(clauses))
+    }
+
+    /// `expanded` is the text with all abbreviations expanded, see [Synonyms::expand]
+    fn strict_query(
+        schema: &TantivySchema,
+        text: &str,
+        expanded: &str,
+    ) -> anyhow::Result<Box<dyn Query>> {
+        let mut parser = QueryParser::for_index(
+            &tantivy_index(),
+            vec![
+                schema.name_field,
+                schema.name_prefix_field,
+                schema.descr_field,
+            ],
+        );
+        // Put some more importance on names, and even more on names starting with the query
+        parser.set_field_boost(schema.name_field, 2.0);
+        parser.set_field_boost(schema.name_prefix_field, 4.0);
+        parser.set_conjunction_by_default();
+
+        Ok(Box::new(BooleanQuery::new(vec![
+            (Occur::Must, parser.parse_query(expanded)?),
+            (
+                Occur::Should,
+                Self::boosted_term(schema.name_keyword_field, text.trim(), 10.0),
+            ),
+            (
+                Occur::Should,
+                Self::boosted_term(
+                    schema.name_prefix_field,
+                    &Self::truncate_prefix(text.trim()),
+                    3.0,
+                ),
+            ),
+        ])))
+    }
+
+    /// Every part of the text has to match, either as typed or as its expansion,
+    /// see [Synonyms::expand]. Allows for typos in longer words
+    fn fuzzy_query(schema: &TantivySchema, alternatives: &[Vec<String>]) -> Option<Box<dyn Query>> {
+        let parts = alternatives
+            .iter()
+            .filter_map(|texts| {
+                let texts = texts
+                    .iter()
+                    .filter_map(|text| Self::fuzzy_words(schema, text))
+                    .map(|query| (Occur::Should, query))
+                    .collect::<Vec<_>>();
+                if texts.is_empty() {
+                    None
+                } else {
+                    Some((
+                        Occur::Must,
+                        Box::new(BooleanQuery::new(texts)) as Box<dyn Query>,
+                    ))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(Box::new(BooleanQuery::new(parts)))
+        }
+    }
+
+    /// Every word has to match a word of the item name
+    fn fuzzy_words(schema: &TantivySchema, text: &str) -> Option<Box<dyn Query>> {
+        let words = text
+            .split_whitespace()
+            .map(|word| {
+                let term = Term::from_field_text(schema.name_words_field, word);
+                let query: Box<dyn Query> =
+                    Box::new(FuzzyTermQuery::new(term, Self::edit_distance(word), true));
+
+                (Occur::Must, query)
+            })
+            .collect::<Vec<_>>();
+
+        if words.is_empty() {
+            None
+        } else {
+            Some(Box::new(BooleanQuery::new(words)))
+        }
+    }
+
+    /// Allowed typos for a given word, short words have to match exactly
+    fn edit_distance(word: &str) -> u8 {
+        match word.chars().count() {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        }
+    }
+
+    /// Replaces every word of the query with the closest word of the found item names
+    fn correct(text: &str, found: &[PlayerItem]) -> Option<String> {
+        let candidates = found
+            .iter()
+            .flat_map(|i| {
+                i.name
+                    .to_lowercase()
+                    .split_whitespace()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let corrected = text
+            .split_whitespace()
+            .map(|word| {
+                candidates
+                    .iter()
+                    .map(|c| (levenshtein(word, c), c))
+                    .filter(|(d, _)| *d <= Self::edit_distance(word) as usize)
+                    .min_by_key(|(d, _)| *d)
+                    .map(|(_, c)| c.as_str())
+                    .unwrap_or(word)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if corrected == text.trim() {
+            None
+        } else {
+            Some(corrected)
+        }
+    }
+
+    /// Longer prefixes are not indexed, the first [EDGE_NGRAM_MAX] chars still match
+    fn truncate_prefix(text: &str) -> String {
+        text.chars().take(EDGE_NGRAM_MAX).collect()
+    }
+
+    /// Term query on the whole (untokenized) text, used to rank exact and prefix hits higher
+    fn boosted_term(field: Field, text: &str, boost: Score) -> Box<dyn Query> {
+        let term = TermQuery::new(Term::from_field_text(field, text), IndexRecordOption::Basic);
+
+        Box::new(BoostQuery::new(Box::new(term), boost))
+    }
+}
+
+impl Searcher for IndexReader {
+    type Item = PlayerItem;
+
+    fn search(&self, text: String, page: usize) {
+        let state = self.state.clone();
+        let sort = self.sort;
+
+        let search = move |text: &str, page| {
+            let started = Instant::now();
+            let result = Self::search_for(text, page, sort);
+            record_search("Account", text, started, &result);
+            result
+        };
+        self.state
+            .run_then(text, page, search, move |generation, found| {
+                let ids = found
+                    .items
+                    .iter()
+                    .map(|i| i.id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                if ids.is_empty() {
+                    return;
+                }
+
+                spawn_job(Priority::Normal, move || {
+                    match auth_request::<Vec<Gw2Tp>>(&format!("commerce/prices?ids={ids}")) {
+                        Ok(tp) => {
+                            let tp = tp
+                                .into_iter()
+                                .map(|tp| (tp.id, tp))
+                                .collect::<HashMap<usize, Gw2Tp>>();
+                            if tp.is_empty() {
+                                return;
+                            }
+
+                            // Only if the shown results still belong to this search
+                            state.amend(generation, |items| {
+                                for item in items {
+                                    item.set_tp(tp.get(&item.id).copied());
+                                }
+                            });
+                        }
+                        Err(e) => {
+                            error!("{e}");
+                        }
+                    }
+                });
+            });
+    }
+
+    fn snapshot(&self) -> Arc<SearchSnapshot<PlayerItem>> {
+        self.state.snapshot()
     }
 }
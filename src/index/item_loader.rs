@@ -1,36 +1,148 @@
-use crate::entities::{Gw2Inventory, Gw2Item, Gw2PlayerItem};
+use crate::context::{cancelled, ctx, try_ctx};
+use crate::entities::{Gw2Account, Gw2Inventory, Gw2Item, Gw2PlayerItem};
 use crate::fms_entities::player_item::{Location, PlayerItem};
-use crate::settings::settings::Settings;
+use crate::index::character_watch;
+use crate::index::refresh_progress::{self, RefreshRun, RefreshStep};
+use crate::records::RecordStore;
+use crate::spawn_job;
 use crate::tantivy::add_documents;
-use crate::utils::{auth_request, fetch_items};
+use crate::utils::{auth_request, fetch_items, fetch_prices};
+use crate::workers::Priority;
 use log::{debug, error, info};
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Characters fetched at the same time, more only run into the rate limit of the API
+const PARALLEL_CHARACTER_FETCHES: usize = 4;
+
+/// Documents added between two progress updates while indexing
+const INDEX_PROGRESS_INTERVAL: usize = 100;
+
+/// Resets [AppContext::refreshing](crate::context::AppContext) however the refresh ends
+struct RefreshGuard;
+
+impl RefreshGuard {
+    /// [None] if another refresh is running
+    fn acquire() -> Option<Self> {
+        if ctx().refreshing.swap(true, Ordering::SeqCst) {
+            info!("A refresh is running already");
+            None
+        } else {
+            Some(Self)
+        }
+    }
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        if let Some(ctx) = try_ctx() {
+            ctx.refreshing.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+pub fn is_refreshing() -> bool {
+    ctx().refreshing.load(Ordering::SeqCst)
+}
+
+/// Runs `f` unless a refresh is running, no refresh starts until it is done. [None] if skipped
+pub fn without_refresh<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let _guard = RefreshGuard::acquire()?;
+    Some(f())
+}
+
+/// Starts a refresh in the background, unless one is running already
+pub fn refresh_now() {
+    if !is_refreshing() {
+        spawn_job(Priority::Background, fetch_all_items);
+    }
+}
+
+/// Starts a refresh once the auto refresh interval passed since the last one
+pub fn refresh_if_due() {
+    let interval = {
+        let ctx = ctx();
+        let settings = ctx.settings.read().unwrap();
+        match settings.auto_refresh_interval() {
+            Some(interval) if !settings.api_key.is_empty() => interval,
+            _ => return,
+        }
+    };
+
+    // The first refresh is started by load
+    let due = ctx()
+        .last_refresh_attempt
+        .lock()
+        .unwrap()
+        .is_some_and(|at| at.elapsed() >= interval);
+    if due && !is_refreshing() {
+        // Set right away, the job might not start before the next frame
+        *ctx().last_refresh_attempt.lock().unwrap() = Some(Instant::now());
+        info!("Auto refresh is due");
+        refresh_now();
+    }
+}
 
 /// Fetches all items at all locations defined in [Location]
 pub fn fetch_all_items() {
+    let Some(_guard) = RefreshGuard::acquire() else {
+        return;
+    };
+    *ctx().last_refresh_attempt.lock().unwrap() = Some(Instant::now());
+    let run = RefreshRun::begin(RefreshStep::Account);
+
     info!("Refreshing item index...");
+    let started = Instant::now();
+
+    // Records are stored per account, a new key might belong to another one
+    let account = match auth_request::<Gw2Account>("account") {
+        Ok(account) => account,
+        Err(e) => {
+            error!("Failed to fetch account, can't refresh: {e}");
+            return;
+        }
+    };
 
     // Item collector
     let items = Arc::new(Mutex::new(Vec::<Vec<PlayerItem>>::new()));
 
-    // Join handles
-    let mut handles = vec![];
-
-    let characters = auth_request::<Vec<String>>("characters");
-    // Spawn a new thread per char, this is a significant performance boost compared to the calls below
-    for character in characters.unwrap_or(vec![]) {
-        let items = items.clone();
-        handles.push(std::thread::spawn(move || {
-            let found = fetch_from_character(character.clone());
-            items.lock().unwrap().push(found);
-        }))
-    }
+    let characters = auth_request::<Vec<String>>("characters").unwrap_or_default();
+    refresh_progress::step(RefreshStep::Characters);
+    refresh_progress::characters_found(characters.len());
+    // Fetching a few chars at once is a significant performance boost compared to the calls below.
+    // The threads are scoped, so they are done once this job is
+    let pending = Mutex::new(characters.into_iter());
+    std::thread::scope(|scope| {
+        for _ in 0..PARALLEL_CHARACTER_FETCHES {
+            scope.spawn(|| {
+                while !cancelled() {
+                    let Some(character) = pending.lock().unwrap().next() else {
+                        break;
+                    };
+                    match try_fetch_from_character(&character) {
+                        Ok(found) => {
+                            items.lock().unwrap().push(found);
+                            refresh_progress::character_done();
+                        }
+                        Err(e) => {
+                            error!("Failed to fetch {character}: {e}");
+                            refresh_progress::character_failed();
+                        }
+                    }
+                }
+            });
+        }
+    });
 
-    for handle in handles {
-        handle.join().unwrap();
+    // Partial results must not replace the stored records
+    if cancelled() {
+        info!("Refresh cancelled");
+        return;
     }
 
+    refresh_progress::step(RefreshStep::SharedInventory);
     info!("Fetching items from shared inventory...");
     items
         .clone()
@@ -38,6 +150,7 @@ pub fn fetch_all_items() {
         .unwrap()
         .push(fetch_from("account/inventory", Location::SharedInventory));
 
+    refresh_progress::step(RefreshStep::Bank);
     info!("Fetching items from bank...");
     items
         .clone()
@@ -45,6 +158,7 @@ pub fn fetch_all_items() {
         .unwrap()
         .push(fetch_from("account/bank", Location::Bank));
 
+    refresh_progress::step(RefreshStep::Materials);
     info!("Fetching items from material storage...");
     items.clone().lock().unwrap().push(fetch_materials());
 
@@ -62,11 +176,30 @@ pub fn fetch_all_items() {
         }
     }
 
-    // Index everything
-    match store(items_map.iter().map(|e| e.1).collect()) {
+    refresh_progress::step(RefreshStep::Prices);
+    info!("Fetching trading post prices...");
+    let ids = items_map.keys().cloned().collect();
+    for tp in fetch_prices(ids) {
+        if let Some(item) = items_map.get_mut(&tp.id) {
+            item.set_tp(Some(tp));
+        }
+    }
+
+    if cancelled() {
+        info!("Refresh cancelled");
+        return;
+    }
+
+    // Store and index everything
+    refresh_progress::step(RefreshStep::Indexing);
+    match store(&account.id, items_map.into_values().collect()) {
         Ok(_) => {
             // Push update
-            Settings::get_mut().update_last_update();
+            let ctx = ctx();
+            let mut settings = ctx.settings.write().unwrap();
+            settings.account = Some(account.id);
+            settings.update_last_update(started.elapsed());
+            run.finish();
         }
         Err(e) => {
             error!("Failed to refresh index due to:\n{}!", e)
@@ -74,23 +207,89 @@ pub fn fetch_all_items() {
     };
 }
 
-/// Fetches all items for the given character
-fn fetch_from_character(character: String) -> Vec<PlayerItem> {
-    info!("Fetching items from char {}...", character);
+/// Fetches the inventories of the given characters and replaces their part of the records
+pub fn refresh_characters(characters: Vec<String>) {
+    let Some(_guard) = RefreshGuard::acquire() else {
+        // Try again later
+        characters.into_iter().for_each(character_watch::queue);
+        return;
+    };
 
-    match auth_request::<Gw2Inventory>(format!("characters/{character}/inventory").as_str()) {
-        Err(_) => Vec::new(),
-        Ok(inv) => {
-            let found = inv
-                .bags
-                .iter()
-                .flat_map(|b| b.inventory.clone())
-                .flatten()
-                .collect::<Vec<Gw2PlayerItem>>();
+    // Records belong to the account of the last full refresh
+    let Some(account) = ctx().settings.read().unwrap().account.clone() else {
+        // Not refreshed yet, the first full refresh picks them up
+        characters.into_iter().for_each(character_watch::queue);
+        return;
+    };
+    let run = RefreshRun::begin(RefreshStep::Characters);
+    refresh_progress::characters_found(characters.len());
+
+    let mut items = RecordStore::all()
+        .into_iter()
+        .map(|i| (i.id, i))
+        .collect::<HashMap<usize, PlayerItem>>();
+    let mut new_ids = vec![];
+    for character in characters {
+        // Keep the old items if the character can't be fetched
+        let found = match try_fetch_from_character(&character) {
+            Ok(found) => found,
+            Err(e) => {
+                error!("Failed to refresh {character}: {e}");
+                refresh_progress::character_failed();
+                continue;
+            }
+        };
+        refresh_progress::character_done();
 
-            convert(Location::Character(character.clone()), found)
+        let location = Location::Character(character);
+        for item in items.values_mut() {
+            item.locations.remove(&location);
+        }
+        for item in found {
+            match items.get_mut(&item.id) {
+                Some(existing) => existing.add(&item),
+                None => {
+                    new_ids.push(item.id);
+                    items.insert(item.id, item);
+                }
+            }
+        }
+    }
+    items.retain(|_, item| !item.locations.is_empty());
+
+    // Known items keep their prices until the next full refresh
+    refresh_progress::step(RefreshStep::Prices);
+    for tp in fetch_prices(new_ids) {
+        if let Some(item) = items.get_mut(&tp.id) {
+            item.set_tp(Some(tp));
         }
     }
+
+    if cancelled() {
+        info!("Refresh cancelled");
+        return;
+    }
+
+    refresh_progress::step(RefreshStep::Indexing);
+    match store(&account, items.into_values().collect()) {
+        Ok(_) => run.finish(),
+        Err(e) => error!("Failed to refresh characters due to:\n{}!", e),
+    }
+}
+
+/// Fetches all items for the given character
+fn try_fetch_from_character(character: &str) -> anyhow::Result<Vec<PlayerItem>> {
+    info!("Fetching items from char {}...", character);
+
+    let inv = auth_request::<Gw2Inventory>(format!("characters/{character}/inventory").as_str())?;
+    let found = inv
+        .bags
+        .iter()
+        .flat_map(|b| b.inventory.clone())
+        .flatten()
+        .collect::<Vec<Gw2PlayerItem>>();
+
+    Ok(convert(Location::Character(character.to_string()), found))
 }
 
 /// Not included in [fetch_from] because we make sure that there is more than 0 of the items
@@ -127,6 +326,7 @@ fn convert(location: Location, items: Vec<Gw2PlayerItem>) -> Vec<PlayerItem> {
 
     // Fetch all items from gw2 api and map them for better access
     let gw2_items = fetch_items(item_ids);
+    refresh_progress::items_resolved(gw2_items.len());
     let gw2_items_map = gw2_items
         .iter()
         .map(|i| (i.id, i))
@@ -150,10 +350,49 @@ fn convert(location: Location, items: Vec<Gw2PlayerItem>) -> Vec<PlayerItem> {
         .collect()
 }
 
+/// Loads the item records of the account used last, so searches work before the first refresh
+pub fn load_records() {
+    let account = ctx().settings.read().unwrap().account.clone();
+    if let Some(account) = account {
+        if let Err(e) = RecordStore::load(&account) {
+            error!("Failed to load item records: {e}");
+        }
+    }
+}
+
+/// Indexes the stored records again without asking the API
+pub fn index_records() {
+    if !RecordStore::is_loaded() {
+        load_records();
+    }
+
+    if let Err(e) = index(RecordStore::all()) {
+        error!("Failed to index item records: {e}");
+    }
+}
+
+/// Indexes the given items and persists them as records of the account.
+/// Records are only replaced once the index was committed, so both always show the same items
+fn store(account: &str, items: Vec<PlayerItem>) -> anyhow::Result<()> {
+    index(items.clone())?;
+
+    debug!("Storing item records...");
+    RecordStore::replace(account, items)
+}
+
 /// Indexes given items
-fn store(items: Vec<&PlayerItem>) -> anyhow::Result<()> {
+fn index(mut items: Vec<PlayerItem>) -> anyhow::Result<()> {
     debug!("Indexing items...");
-    add_documents(items.iter().map(|i| i.doc()));
+    // Name order is only known here, it's indexed as a fast field for sorting
+    items.sort_by_key(|i| i.name.to_lowercase());
+    let documents = items.iter().enumerate().map(|(rank, i)| {
+        if rank % INDEX_PROGRESS_INTERVAL == 0 {
+            refresh_progress::documents_indexed(rank);
+        }
+        i.doc(rank as u64)
+    });
+    add_documents(documents)?;
+    refresh_progress::documents_indexed(items.len());
     info!("Indexed Items");
 
     Ok(())
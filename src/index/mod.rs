@@ -1,6 +1,11 @@
+pub mod character_watch;
 pub mod find_my_sht;
 pub mod item_loader;
+pub mod refresh_progress;
+pub mod synonyms;
 
 // Used in search only
+mod filter_query;
 mod index_reader;
+mod searcher;
 mod wiki_search;
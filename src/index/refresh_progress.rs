@@ -0,0 +1,233 @@
+use log::info;
+use std::time::{Duration, Instant};
+
+use crate::context::{cancelled, ctx, try_ctx};
+use crate::diagnostics;
+
+/// Steps of a refresh, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshStep {
+    Account,
+    Characters,
+    SharedInventory,
+    Bank,
+    Materials,
+    Prices,
+    Indexing,
+}
+
+impl RefreshStep {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RefreshStep::Account => "Account",
+            RefreshStep::Characters => "Characters",
+            RefreshStep::SharedInventory => "Shared inventory",
+            RefreshStep::Bank => "Bank",
+            RefreshStep::Materials => "Material storage",
+            RefreshStep::Prices => "Trading post prices",
+            RefreshStep::Indexing => "Indexing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    Running,
+    Finished,
+    /// Finished, but some characters could not be fetched
+    PartiallyFailed,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of the running or last refresh
+#[derive(Debug, Clone)]
+pub struct RefreshProgress {
+    pub step: RefreshStep,
+    pub outcome: RefreshOutcome,
+    pub characters_total: usize,
+    pub characters_done: usize,
+    /// Characters that could not be fetched
+    pub characters_failed: usize,
+    /// Item definitions fetched from the API
+    pub items_resolved: usize,
+    pub documents_indexed: usize,
+    /// Duration of every finished step
+    pub timings: Vec<(RefreshStep, Duration)>,
+    started: Instant,
+    step_started: Instant,
+}
+
+impl RefreshProgress {
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Every character counts as much as one of the other steps
+    pub fn fraction(&self) -> f32 {
+        let characters = self.characters_total;
+        let done = match self.step {
+            RefreshStep::Account => 0,
+            RefreshStep::Characters => self.characters_done + self.characters_failed,
+            RefreshStep::SharedInventory => characters,
+            RefreshStep::Bank => characters + 1,
+            RefreshStep::Materials => characters + 2,
+            RefreshStep::Prices => characters + 3,
+            RefreshStep::Indexing => characters + 4,
+        };
+
+        done as f32 / (characters + 5) as f32
+    }
+
+    /// Current step with its details, e.g. "Characters 3/9"
+    pub fn describe(&self) -> String {
+        match self.step {
+            RefreshStep::Characters if self.characters_failed > 0 => format!(
+                "{} {}/{}, {} failed",
+                self.step.label(),
+                self.characters_done + self.characters_failed,
+                self.characters_total,
+                self.characters_failed
+            ),
+            RefreshStep::Characters => format!(
+                "{} {}/{}",
+                self.step.label(),
+                self.characters_done,
+                self.characters_total
+            ),
+            RefreshStep::Prices => {
+                format!("{} {} items", self.step.label(), self.items_resolved)
+            }
+            RefreshStep::Indexing => {
+                format!("{} {} documents", self.step.label(), self.documents_indexed)
+            }
+            _ => self.step.label().to_string(),
+        }
+    }
+
+    fn finish_step(&mut self) {
+        let took = self.step_started.elapsed();
+        info!("Refresh step {} took {took:?}", self.step.label());
+        diagnostics::refresh_step(self.step.label(), took);
+        self.timings.push((self.step, took));
+        self.step_started = Instant::now();
+    }
+}
+
+/// A running refresh, it counts as failed if dropped before [RefreshRun::finish]
+pub struct RefreshRun {
+    finished: bool,
+}
+
+impl RefreshRun {
+    pub fn begin(step: RefreshStep) -> Self {
+        *ctx().refresh_progress.lock().unwrap() = Some(RefreshProgress {
+            step,
+            outcome: RefreshOutcome::Running,
+            characters_total: 0,
+            characters_done: 0,
+            characters_failed: 0,
+            items_resolved: 0,
+            documents_indexed: 0,
+            timings: vec![],
+            started: Instant::now(),
+            step_started: Instant::now(),
+        });
+
+        Self { finished: false }
+    }
+
+    pub fn finish(mut self) {
+        self.finished = true;
+        end(RefreshOutcome::Finished);
+    }
+}
+
+impl Drop for RefreshRun {
+    fn drop(&mut self) {
+        if !self.finished {
+            end(if cancelled() {
+                RefreshOutcome::Cancelled
+            } else {
+                RefreshOutcome::Failed
+            });
+        }
+    }
+}
+
+fn end(outcome: RefreshOutcome) {
+    update(|progress| {
+        progress.finish_step();
+        let outcome = match outcome {
+            RefreshOutcome::Finished if progress.characters_failed > 0 => {
+                RefreshOutcome::PartiallyFailed
+            }
+            outcome => outcome,
+        };
+        progress.outcome = outcome;
+        let summary = format!(
+            "Refresh {outcome:?} after {:?}: {}/{} characters ({} failed), {} item definitions, {} documents indexed",
+            progress.elapsed(),
+            progress.characters_done,
+            progress.characters_total,
+            progress.characters_failed,
+            progress.items_resolved,
+            progress.documents_indexed
+        );
+        info!("{summary}");
+        diagnostics::refresh_finished(
+            summary,
+            matches!(
+                outcome,
+                RefreshOutcome::Failed | RefreshOutcome::PartiallyFailed
+            ),
+        );
+    });
+}
+
+/// Changes the progress of the running refresh, if there is one
+fn update<F: FnOnce(&mut RefreshProgress)>(f: F) {
+    let Some(ctx) = try_ctx() else {
+        return;
+    };
+
+    let mut progress = ctx.refresh_progress.lock().unwrap();
+    if let Some(progress) = progress
+        .as_mut()
+        .filter(|p| p.outcome == RefreshOutcome::Running)
+    {
+        f(progress);
+    }
+}
+
+/// Progress of the running refresh, or the outcome of the last one
+pub fn refresh_progress() -> Option<RefreshProgress> {
+    ctx().refresh_progress.lock().unwrap().clone()
+}
+
+pub fn step(step: RefreshStep) {
+    update(|progress| {
+        progress.finish_step();
+        progress.step = step;
+    });
+}
+
+pub fn characters_found(total: usize) {
+    update(|progress| progress.characters_total = total);
+}
+
+pub fn character_done() {
+    update(|progress| progress.characters_done += 1);
+}
+
+pub fn character_failed() {
+    update(|progress| progress.characters_failed += 1);
+}
+
+pub fn items_resolved(count: usize) {
+    update(|progress| progress.items_resolved += count);
+}
+
+pub fn documents_indexed(count: usize) {
+    update(|progress| progress.documents_indexed = count);
+}
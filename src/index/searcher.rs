@@ -0,0 +1,262 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::context::cancelled;
+use crate::diagnostics;
+use crate::index::index_reader::FacetGroup;
+use crate::spawn_job;
+use crate::ui_utils::Renderable;
+use crate::workers::Priority;
+use nexus::imgui::Ui;
+
+/// Searches one kind of items, see [SearchProvider] for showing them
+pub trait Searcher {
+    type Item: Renderable;
+
+    /// Starts a search in the background, the results show up in the next snapshots
+    fn search(&self, query: String, page: usize);
+
+    /// Results of the last finished search, cheap to call every frame
+    fn snapshot(&self) -> Arc<SearchSnapshot<Self::Item>>;
+}
+
+/// Adds the duration and outcome of a search to the diagnostics
+pub fn record_search<T>(
+    provider: &str,
+    query: &str,
+    started: Instant,
+    result: &anyhow::Result<SearchPage<T>>,
+) {
+    let (hits, error) = match result {
+        Ok(found) => (found.total_hits.or(Some(found.items.len())), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    diagnostics::search(provider, query, started.elapsed(), hits, error);
+}
+
+/// A registered search provider, independent of the type of its items
+pub trait SearchProvider: Send {
+    /// Shown in the mode selection and as section title
+    fn name(&self) -> &'static str;
+
+    fn search(&self, query: String, page: usize);
+
+    /// Draws the results, returns a filter to narrow the query down with if one was clicked
+    fn render_results(&self, ui: &Ui) -> Option<String>;
+
+    /// Options shown above the results, returns true if the search has to be repeated
+    fn render_options(&mut self, _ui: &Ui) -> bool {
+        false
+    }
+
+    /// Starts looking up completions for a partially typed name, see [SearchProvider::suggestions]
+    fn suggest(&self, _text: String, _limit: usize) {}
+
+    /// Completions of the given text found so far
+    fn suggestions(&self, _text: &str) -> Vec<String> {
+        vec![]
+    }
+
+    /// Whether the results change after the index was refreshed
+    fn uses_index(&self) -> bool {
+        false
+    }
+}
+
+/// What a provider found for one page of a query
+pub struct SearchPage<T> {
+    pub items: Vec<T>,
+    /// Hits of the query over all pages, if known
+    pub total_hits: Option<usize>,
+    pub has_more: bool,
+    /// Query the results were actually found for, if it differs from the input
+    pub corrected: Option<String>,
+    pub facets: Vec<FacetGroup>,
+}
+
+impl<T> Default for SearchPage<T> {
+    fn default() -> Self {
+        Self {
+            items: vec![],
+            total_hits: None,
+            has_more: false,
+            corrected: None,
+            facets: vec![],
+        }
+    }
+}
+
+/// Immutable results of a search, shared with the UI without copying the items
+pub struct SearchSnapshot<T> {
+    generation: usize,
+    pub query: String,
+    pub page: usize,
+    pub items: Arc<[T]>,
+    pub total_hits: Option<usize>,
+    pub has_more: bool,
+    /// A newer search is running, the results still belong to the old one
+    pub loading: bool,
+    /// Why the search failed, e.g. an invalid filter
+    pub error: Option<String>,
+    pub corrected: Option<String>,
+    pub facets: Arc<[FacetGroup]>,
+}
+
+impl<T> SearchSnapshot<T> {
+    fn with_loading(&self, loading: bool) -> Self {
+        Self {
+            generation: self.generation,
+            query: self.query.clone(),
+            page: self.page,
+            items: self.items.clone(),
+            total_hits: self.total_hits,
+            has_more: self.has_more,
+            loading,
+            error: self.error.clone(),
+            corrected: self.corrected.clone(),
+            facets: self.facets.clone(),
+        }
+    }
+}
+
+impl<T> Default for SearchSnapshot<T> {
+    fn default() -> Self {
+        Self {
+            generation: 0,
+            query: "".to_string(),
+            page: 0,
+            items: Arc::new([]),
+            total_hits: None,
+            has_more: false,
+            loading: false,
+            error: None,
+            corrected: None,
+            facets: Arc::new([]),
+        }
+    }
+}
+
+/// Snapshot of a provider plus the numbering of its searches.
+/// Results of superseded searches are dropped, so a slow old search never replaces a newer one
+pub struct SearchState<T> {
+    requested: Arc<AtomicUsize>,
+    snapshot: Arc<Mutex<Arc<SearchSnapshot<T>>>>,
+}
+
+impl<T> Clone for SearchState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            requested: self.requested.clone(),
+            snapshot: self.snapshot.clone(),
+        }
+    }
+}
+
+impl<T> Default for SearchState<T> {
+    fn default() -> Self {
+        Self {
+            requested: Arc::new(AtomicUsize::new(0)),
+            snapshot: Arc::new(Mutex::new(Arc::new(SearchSnapshot::default()))),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> SearchState<T> {
+    pub fn snapshot(&self) -> Arc<SearchSnapshot<T>> {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    /// Starts a new search, all older ones are superseded. Returns its generation
+    pub fn start(&self) -> usize {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        let generation = self.requested.fetch_add(1, Ordering::SeqCst) + 1;
+        *snapshot = Arc::new(snapshot.with_loading(true));
+
+        generation
+    }
+
+    /// Whether no newer search was started since
+    pub fn is_current(&self, generation: usize) -> bool {
+        self.requested.load(Ordering::SeqCst) == generation
+    }
+
+    /// Publishes the result of a search, unless it was superseded. Returns the published snapshot
+    pub fn finish(
+        &self,
+        generation: usize,
+        query: String,
+        page: usize,
+        result: anyhow::Result<SearchPage<T>>,
+    ) -> Option<Arc<SearchSnapshot<T>>> {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        if !self.is_current(generation) {
+            return None;
+        }
+
+        let (found, error) = match result {
+            Ok(found) => (found, None),
+            Err(e) => (SearchPage::default(), Some(e.to_string())),
+        };
+        *snapshot = Arc::new(SearchSnapshot {
+            generation,
+            query,
+            page,
+            items: found.items.into(),
+            total_hits: found.total_hits,
+            has_more: found.has_more,
+            loading: false,
+            error,
+            corrected: found.corrected,
+            facets: found.facets.into(),
+        });
+
+        Some(snapshot.clone())
+    }
+
+    /// Runs `search` as an interactive job and publishes its result
+    pub fn run<F>(&self, query: String, page: usize, search: F)
+    where
+        F: FnOnce(&str, usize) -> anyhow::Result<SearchPage<T>> + Send + 'static,
+    {
+        self.run_then(query, page, search, |_, _| {});
+    }
+
+    /// Like [SearchState::run], `done` is called with the generation and the results once published.
+    /// Follow up work can change them with [SearchState::amend]
+    pub fn run_then<F, D>(&self, query: String, page: usize, search: F, done: D)
+    where
+        F: FnOnce(&str, usize) -> anyhow::Result<SearchPage<T>> + Send + 'static,
+        D: FnOnce(usize, Arc<SearchSnapshot<T>>) + Send + 'static,
+    {
+        let state = self.clone();
+        let generation = state.start();
+        spawn_job(Priority::Interactive, move || {
+            // A newer search was started in the meantime
+            if cancelled() || !state.is_current(generation) {
+                return;
+            }
+
+            let result = search(&query, page);
+            if let Some(published) = state.finish(generation, query, page, result) {
+                done(generation, published);
+            }
+        });
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> SearchState<T> {
+    /// Changes the shown items, if they still belong to the given search
+    pub fn amend<F: FnOnce(&mut [T])>(&self, generation: usize, f: F) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        if snapshot.generation != generation {
+            return;
+        }
+
+        let mut items = snapshot.items.to_vec();
+        f(&mut items);
+        let mut amended = snapshot.with_loading(snapshot.loading);
+        amended.items = items.into();
+        *snapshot = Arc::new(amended);
+    }
+}
@@ -0,0 +1,213 @@
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::context::ctx;
+use crate::utils::sub_path;
+
+/// Community abbreviations shipped with the addon, can be extended or overridden by the user
+const BUILTIN: &[(&str, &str)] = &[
+    ("ecto", "glob of ectoplasm"),
+    ("ectos", "glob of ectoplasm"),
+    ("mc", "mystic coin"),
+    ("mcs", "mystic coin"),
+    ("clover", "mystic clover"),
+    ("clovers", "mystic clover"),
+    ("laurels", "laurel"),
+    ("lodes", "lodestone"),
+    ("lodestones", "lodestone"),
+    ("obby", "obsidian shard"),
+    ("obsi", "obsidian shard"),
+    ("amalg", "amalgamated gemstone"),
+    ("amalgs", "amalgamated gemstone"),
+    ("gof", "gift of fortune"),
+    ("gom", "gift of mastery"),
+    ("bl key", "black lion chest key"),
+    ("bl keys", "black lion chest key"),
+    ("unid", "unidentified gear"),
+    ("unids", "unidentified gear"),
+    ("t6 blood", "vial of powerful blood"),
+    ("t6 bone", "ancient bone"),
+    ("t6 bones", "ancient bone"),
+    ("t6 claw", "vicious claw"),
+    ("t6 claws", "vicious claw"),
+    ("t6 dust", "pile of crystalline dust"),
+    ("t6 fang", "vicious fang"),
+    ("t6 fangs", "vicious fang"),
+    ("t6 scale", "armored scale"),
+    ("t6 scales", "armored scale"),
+    ("t6 totem", "elaborate totem"),
+    ("t6 totems", "elaborate totem"),
+    ("t6 venom", "powerful venom sac"),
+    ("t5 blood", "vial of potent blood"),
+    ("t5 bone", "large bone"),
+    ("t5 bones", "large bone"),
+    ("t5 claw", "large claw"),
+    ("t5 claws", "large claw"),
+    ("t5 dust", "pile of incandescent dust"),
+    ("t5 fang", "large fang"),
+    ("t5 fangs", "large fang"),
+    ("t5 scale", "large scale"),
+    ("t5 scales", "large scale"),
+    ("t5 totem", "intricate totem"),
+    ("t5 totems", "intricate totem"),
+    ("t5 venom", "potent venom sac"),
+];
+
+/// File below the addon dir containing user defined abbreviations as `{"abbr": "expansion"}`
+const USER_FILE: &str = "synonyms.json";
+
+/// Chars with a meaning in the tantivy query syntax
+const QUERY_SPECIAL_CHARS: &[char] = &[
+    '+', '^', '`', ':', '{', '}', '"', '[', ']', '(', ')', '!', '\\', '*',
+];
+
+/// Expands abbreviations in a search query
+pub struct Synonyms {
+    entries: HashMap<String, String>,
+    max_words: usize,
+    /// Modification time of [USER_FILE] when it was read
+    modified: Option<SystemTime>,
+}
+
+/// A search text with all abbreviations replaced
+pub struct Expansion {
+    /// Query for the tantivy query parser, matching either the abbreviation or its expansion
+    pub query: String,
+    /// All words of the text and of the used expansions
+    pub words: Vec<String>,
+    /// Parts of the text in order, either a single word or an abbreviation and its expansion
+    pub alternatives: Vec<Vec<String>>,
+}
+
+impl Synonyms {
+    /// The current synonyms, edits of [USER_FILE] are picked up without a restart
+    pub fn get() -> Arc<Self> {
+        let modified = Self::modified();
+        let ctx = ctx();
+        if let Some(synonyms) = ctx
+            .synonyms
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|s| s.modified == modified)
+        {
+            return synonyms.clone();
+        }
+
+        let synonyms = Arc::new(Self::load());
+        *ctx.synonyms.write().unwrap() = Some(synonyms.clone());
+        synonyms
+    }
+
+    fn modified() -> Option<SystemTime> {
+        let path = unsafe { sub_path(USER_FILE) };
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn load() -> Self {
+        let mut entries = BUILTIN
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        let path = unsafe { sub_path(USER_FILE) };
+        if path.exists() {
+            match Self::read_user(&path) {
+                Ok(user) => {
+                    info!("Loaded {} user synonyms", user.len());
+                    entries.extend(
+                        user.into_iter()
+                            .map(|(k, v)| (Self::normalize(&k), v.to_lowercase())),
+                    );
+                }
+                Err(e) => error!("Failed to read {USER_FILE}: {e}"),
+            }
+        } else if let Ok(file) = File::create(&path) {
+            // Give users a file to start with
+            let _ = serde_json::to_writer_pretty(file, &HashMap::<String, String>::new());
+        }
+
+        let max_words = entries
+            .keys()
+            .map(|k| k.split_whitespace().count())
+            .max()
+            .unwrap_or(1);
+
+        Self {
+            entries,
+            max_words,
+            modified: Self::modified(),
+        }
+    }
+
+    fn read_user(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+        let str = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&str)?)
+    }
+
+    fn normalize(key: &str) -> String {
+        key.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    }
+
+    /// Removes the query syntax from user defined texts, so they are only matched as words.
+    /// The query parser does not support escaping within words
+    fn sanitize(text: &str) -> String {
+        text.replace(QUERY_SPECIAL_CHARS, " ")
+            .split_whitespace()
+            // Would exclude the word or start a quoted phrase
+            .map(|word| word.trim_start_matches(['-', '\'']))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Replaces every known abbreviation with `(abbreviation OR (expansion))`, longest match first
+    pub fn expand(&self, text: &str) -> Expansion {
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        let mut query = vec![];
+        let mut all_words = words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let mut alternatives = vec![];
+
+        let mut i = 0;
+        while i < words.len() {
+            let max_end = usize::min(words.len(), i + self.max_words);
+            let found = (i + 1..=max_end).rev().find_map(|end| {
+                let key = words[i..end].join(" ");
+                self.entries
+                    .get(&key)
+                    .map(|expansion| (end, key, expansion))
+            });
+
+            match found {
+                Some((end, key, expansion)) => {
+                    query.push(format!(
+                        "(({}) OR ({}))",
+                        Self::sanitize(&key),
+                        Self::sanitize(expansion)
+                    ));
+                    all_words.extend(expansion.split_whitespace().map(|w| w.to_string()));
+                    alternatives.push(vec![key, expansion.clone()]);
+                    i = end;
+                }
+                None => {
+                    query.push(words[i].to_string());
+                    alternatives.push(vec![words[i].to_string()]);
+                    i += 1;
+                }
+            }
+        }
+
+        Expansion {
+            query: query.join(" "),
+            words: all_words,
+            alternatives,
+        }
+    }
+}
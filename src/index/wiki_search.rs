@@ -1,19 +1,25 @@
+use crate::context::ctx;
+use crate::diagnostics;
 use crate::fms_entities::wiki_item::WikiItem;
-use crate::settings::settings::Settings;
-use crate::spawn_thread;
-use crate::utils::Searcher;
+use crate::index::searcher::{record_search, SearchPage, SearchSnapshot, SearchState, Searcher};
+use crate::utils::REQUEST_TIMEOUT;
 use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct WikiContinue {
     sroffset: usize,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WikiSearchInfo {
+    totalhits: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct WikiSearch {
+    searchinfo: Option<WikiSearchInfo>,
     search: Vec<WikiItem>,
 }
 
@@ -25,67 +31,59 @@ struct WikiResult {
 }
 
 pub struct WikiReader {
-    last_result: Arc<Mutex<Vec<WikiItem>>>,
-    has_more: Arc<AtomicBool>,
-    loading: Arc<Mutex<bool>>,
+    state: SearchState<WikiItem>,
 }
 
 impl WikiReader {
     pub fn new() -> Self {
         Self {
-            last_result: Arc::new(Mutex::new(vec![])),
-            has_more: Arc::new(AtomicBool::new(false)),
-            loading: Arc::new(Mutex::new(false)),
+            state: SearchState::default(),
         }
     }
 
-    fn search_for(
-        text: String,
-        page: usize,
-        has_more: Arc<AtomicBool>,
-    ) -> anyhow::Result<Vec<WikiItem>> {
-        let limit = Settings::get().item_load_limit;
-        let text = text.replace(" ", "%20");
-        let url = format!("https://wiki.guildwars2.com/api.php?action=query&list=search&srsearch={text}&utf8=&format=json&srlimit={limit}&sroffset={page}");
-
-        let found = ureq::get(&url).call()?.into_json::<WikiResult>()?;
-        if found.more.is_some() {
-            has_more.clone().store(true, Ordering::SeqCst);
+    fn search_for(text: &str, page: usize) -> anyhow::Result<SearchPage<WikiItem>> {
+        // Only filters were entered
+        if text.trim().is_empty() {
+            return Ok(SearchPage::default());
         }
 
-        Ok(found.query.search)
-    }
-}
+        let limit = ctx().settings.read().unwrap().item_load_limit as usize;
+        let offset = page * limit;
+        let text = text.to_lowercase().replace(" ", "%20");
+        let url = format!("https://wiki.guildwars2.com/api.php?action=query&list=search&srsearch={text}&utf8=&format=json&srlimit={limit}&sroffset={offset}");
 
-impl Searcher<Vec<WikiItem>> for WikiReader {
-    fn is_loading(&self) -> bool {
-        self.loading.clone().lock().unwrap().clone()
-    }
+        let started = Instant::now();
+        let response = ureq::get(&url).timeout(REQUEST_TIMEOUT).call();
+        let status = match &response {
+            Ok(response) => Some(response.status()),
+            Err(ureq::Error::Status(code, _)) => Some(*code),
+            Err(ureq::Error::Transport(_)) => None,
+        };
+        diagnostics::request("wiki/api.php", status, started.elapsed(), 0);
+        let found = response?.into_json::<WikiResult>()?;
 
-    fn has_more(&self) -> bool {
-        self.has_more.load(Ordering::SeqCst)
+        Ok(SearchPage {
+            items: found.query.search,
+            total_hits: found.query.searchinfo.map(|info| info.totalhits),
+            has_more: found.more.is_some(),
+            ..SearchPage::default()
+        })
     }
+}
 
-    fn search(&self, text: String, page: usize) {
-        let last_result = self.last_result.clone();
-        let loading = self.loading.clone();
-        let has_more = self.has_more.clone();
-
-        spawn_thread(move || {
-            // Uuuuh no idea why I'm doing this, but never change a running system
-            while *loading.lock().unwrap() {
-                std::thread::sleep(Duration::from_millis(20));
-            }
-
-            *loading.lock().unwrap() = true;
-            let result = WikiReader::search_for(text.clone().to_lowercase(), page, has_more);
-            *loading.lock().unwrap() = false;
+impl Searcher for WikiReader {
+    type Item = WikiItem;
 
-            *last_result.lock().unwrap() = result.unwrap_or_else(|_| vec![])
-        })
+    fn search(&self, text: String, page: usize) {
+        self.state.run(text, page, |text, page| {
+            let started = Instant::now();
+            let result = WikiReader::search_for(text, page);
+            record_search("Wiki", text, started, &result);
+            result
+        });
     }
 
-    fn last_result(&self) -> Vec<WikiItem> {
-        (*self.last_result.clone().lock().unwrap()).to_owned()
+    fn snapshot(&self) -> Arc<SearchSnapshot<WikiItem>> {
+        self.state.snapshot()
     }
 }
@@ -1,8 +1,14 @@
-use crate::index::find_my_sht::ItemSearch;
-use crate::index::item_loader::fetch_all_items;
+use crate::context::{ctx, AppContext};
+use crate::index::character_watch::{self, refresh_characters_if_due};
+use crate::index::item_loader::{
+    fetch_all_items, index_records, load_records, refresh_if_due, refresh_now,
+};
+use crate::records::RecordStore;
 use crate::settings::settings::Settings;
 use crate::tantivy::cleanup_tantivy;
 use crate::utils::sub_path;
+use crate::workers::Priority;
+use log::warn;
 use nexus::imgui::Ui;
 use nexus::keybind::register_keybind_with_string;
 use nexus::texture::load_texture_from_memory;
@@ -10,17 +16,21 @@ use nexus::{
     gui::{register_render, RenderType},
     keybind_handler, render, AddonFlags,
 };
-use std::sync::OnceLock;
-use std::thread::JoinHandle;
+use std::time::Duration;
 
+mod chat_link;
 mod constants;
+mod context;
+mod diagnostics;
 mod entities;
 mod fms_entities;
 mod index;
+mod records;
 mod settings;
 mod tantivy;
 mod ui_utils;
 mod utils;
+mod workers;
 
 nexus::export!(
     name: "Find my Sh*t",
@@ -30,8 +40,6 @@ nexus::export!(
     flags: AddonFlags::None,
 );
 
-static mut THREADS: OnceLock<Vec<JoinHandle<()>>> = OnceLock::new();
-
 static BANK_ICON_BYTES: &'static [u8] = include_bytes!("../icons/bank.png");
 static MAT_STORE_ICON_BYTES: &'static [u8] = include_bytes!("../icons/mat_store.png");
 static SHARED_INV_ICON_BYTES: &'static [u8] = include_bytes!("../icons/shared_inv.png");
@@ -52,6 +60,9 @@ const GOLD_ICON_ID: &str = "GOLD_ICON";
 const SILVER_ICON_ID: &str = "SILVER_ICON";
 const COPPER_ICON_ID: &str = "COPPER_ICON";
 
+/// How long unloading waits for running jobs
+const UNLOAD_TIMEOUT: Duration = Duration::from_secs(2);
+
 fn load() {
     load_texture_from_memory(BANK_ICON_ID, BANK_ICON_BYTES, None);
     load_texture_from_memory(MAT_STORE_ID, MAT_STORE_ICON_BYTES, None);
@@ -63,15 +74,20 @@ fn load() {
     load_texture_from_memory(SILVER_ICON_ID, SILVER_ICON_BYTES, None);
     load_texture_from_memory(COPPER_ICON_ID, COPPER_ICON_BYTES, None);
 
-    unsafe {
-        let _ = THREADS.set(vec![]);
+    let settings =
+        Settings::from_path(unsafe { sub_path("settings.json") }).unwrap_or_else(Settings::new);
+    AppContext::create(settings);
 
-        if let Some(settings) = Settings::from_path(sub_path("settings.json")) {
-            *Settings::get_mut() = settings;
+    spawn_job(Priority::Background, || {
+        load_records();
+        // Nothing was kept from the last session, make the stored items searchable right away
+        if ctx().settings.read().unwrap().index_profile.in_memory {
+            index_records();
         }
+        fetch_all_items();
+    });
 
-        spawn_thread(fetch_all_items);
-    }
+    character_watch::subscribe();
 
     register_render(RenderType::OptionsRender, render!(render_options)).revert_on_unload();
     register_render(RenderType::Render, render!(render_search)).revert_on_unload();
@@ -81,42 +97,60 @@ fn load() {
             return;
         }
 
-        let search = ItemSearch::get_mut();
+        let ctx = ctx();
+        let mut search = ctx.search.lock().unwrap();
         search.show = !search.show;
     });
     register_keybind_with_string("KB_OPEN_SEARCH", handler, "ALT+S").revert_on_unload();
+
+    let refresh_handler = keybind_handler!(|_, release| {
+        if release {
+            refresh_now();
+        }
+    });
+    register_keybind_with_string("KB_REFRESH_ITEMS", refresh_handler, "ALT+SHIFT+S")
+        .revert_on_unload();
 }
 
 fn render_options(ui: &Ui) {
-    Settings::get_mut().render(ui);
+    ctx().settings.write().unwrap().render(ui);
 }
 
 fn render_search(ui: &Ui) {
-    ItemSearch::get_mut().render(ui);
+    refresh_if_due();
+    refresh_characters_if_due();
+    ctx().search.lock().unwrap().render(ui);
+    ctx().diagnostics.lock().unwrap().render(ui);
 }
 
 fn unload() {
-    unsafe {
-        for t in THREADS.take().unwrap() {
-            let _ = t.join();
-        }
+    let ctx = ctx();
 
-        if let Some(settings) = Settings::take() {
-            let _ = settings.store(sub_path("settings.json"));
-        }
+    // Don't freeze the game, jobs still running after the timeout are left behind.
+    // They find the context gone and stop at their next cancellation check
+    ctx.cancel.cancel();
+    if !ctx.workers.shutdown(UNLOAD_TIMEOUT) {
+        warn!("Some jobs didn't stop within {UNLOAD_TIMEOUT:?}");
+    }
 
-        cleanup_tantivy();
+    let _ = ctx
+        .settings
+        .read()
+        .unwrap()
+        .store(unsafe { sub_path("settings.json") });
 
-        let _ = ItemSearch::take();
-    }
+    cleanup_tantivy();
+    RecordStore::clear();
+
+    drop(ctx);
+    let _ = AppContext::destroy();
 }
 
-pub fn spawn_thread<F>(f: F)
+/// Runs the given job on the worker pool
+pub fn spawn_job<F>(priority: Priority, f: F)
 where
-    F: FnOnce() -> (),
+    F: FnOnce(),
     F: Send + 'static,
 {
-    unsafe {
-        THREADS.get_mut().unwrap().push(std::thread::spawn(f));
-    }
+    ctx().workers.spawn(priority, f);
 }
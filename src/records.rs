@@ -0,0 +1,135 @@
+use anyhow::bail;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read, rename, write};
+use std::path::PathBuf;
+
+use crate::context::ctx;
+use crate::fms_entities::player_item::PlayerItem;
+use crate::utils::sub_path;
+
+/// Bump on incompatible changes of [PlayerItem] and convert older files in [RecordStore::migrate].
+/// Added fields only need a `#[serde(default)]`, records are stored with field names
+const RECORD_VERSION: u32 = 1;
+
+/// Only the version, readable for every format
+#[derive(Deserialize)]
+struct RecordHeader {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordFile {
+    version: u32,
+    account: String,
+    items: Vec<PlayerItem>,
+}
+
+/// All items of one account, this is the source of truth the search index is built from.
+/// The loaded one is kept in the [crate::context::AppContext]
+pub struct RecordStore {
+    items: HashMap<usize, PlayerItem>,
+}
+
+impl RecordStore {
+    /// Loads the records of the given account, migrating them if needed
+    pub fn load(account: &str) -> anyhow::Result<()> {
+        let path = Self::path(account);
+        let items = if path.exists() {
+            Self::read(account)?
+        } else {
+            vec![]
+        };
+        info!("Loaded {} item records", items.len());
+
+        Self::set(items);
+        Ok(())
+    }
+
+    /// Replaces all records of the given account and persists them
+    pub fn replace(account: &str, items: Vec<PlayerItem>) -> anyhow::Result<()> {
+        Self::write(account, &items)?;
+        Self::set(items);
+
+        Ok(())
+    }
+
+    /// Records for the given ids in the same order, unknown ids are skipped
+    pub fn items(ids: &[usize]) -> Vec<PlayerItem> {
+        match ctx().records.read().unwrap().as_ref() {
+            Some(store) => ids
+                .iter()
+                .filter_map(|id| store.items.get(id).cloned())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Every record of the loaded account
+    pub fn all() -> Vec<PlayerItem> {
+        match ctx().records.read().unwrap().as_ref() {
+            Some(store) => store.items.values().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Whether the records of an account were loaded, they might still be empty
+    pub fn is_loaded() -> bool {
+        ctx().records.read().unwrap().is_some()
+    }
+
+    pub fn clear() {
+        *ctx().records.write().unwrap() = None;
+    }
+
+    fn set(items: Vec<PlayerItem>) {
+        *ctx().records.write().unwrap() = Some(Self {
+            items: items.into_iter().map(|i| (i.id, i)).collect(),
+        });
+    }
+
+    fn path(account: &str) -> PathBuf {
+        unsafe { sub_path("records").join(format!("{account}.msgpack")) }
+    }
+
+    fn read(account: &str) -> anyhow::Result<Vec<PlayerItem>> {
+        let bytes = read(Self::path(account))?;
+        let header: RecordHeader = rmp_serde::from_slice(&bytes)?;
+        if header.version == RECORD_VERSION {
+            return Ok(rmp_serde::from_slice::<RecordFile>(&bytes)?.items);
+        }
+
+        let items = Self::migrate(header.version, &bytes)?;
+        info!(
+            "Migrated item records from version {} to {RECORD_VERSION}",
+            header.version
+        );
+        Self::write(account, &items)?;
+
+        Ok(items)
+    }
+
+    /// Converts records of an older version, one version at a time
+    fn migrate(version: u32, _bytes: &[u8]) -> anyhow::Result<Vec<PlayerItem>> {
+        // No older versions yet, convert them here once the format changes
+        bail!("Unknown item record version {version}")
+    }
+
+    /// Writes to a temporary file first, so a crash never leaves half written records
+    fn write(account: &str, items: &[PlayerItem]) -> anyhow::Result<()> {
+        let path = Self::path(account);
+        create_dir_all(path.parent().unwrap())?;
+
+        let file = RecordFile {
+            version: RECORD_VERSION,
+            account: account.to_string(),
+            items: items.to_vec(),
+        };
+        let tmp = path.with_extension("tmp");
+        write(&tmp, rmp_serde::to_vec_named(&file)?)?;
+        rename(tmp, path)?;
+
+        Ok(())
+    }
+}
@@ -3,7 +3,8 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::entities::{Gw2ApiKey, Gw2Permission, LoadingState};
-use crate::{utils, THREADS};
+use crate::workers::Priority;
+use crate::{spawn_job, utils};
 
 #[derive(Debug, Clone)]
 pub struct ApiKeyLoader {
@@ -39,31 +40,29 @@ impl ApiKeyLoader {
         }
 
         let loading_state = self.loading_state.clone();
-        unsafe {
-            THREADS.get_mut().unwrap().push(thread::spawn(move || {
-                *loading_state.lock().unwrap() = LoadingState::Loading;
-                let result = utils::request::<Gw2ApiKey>(check_api_key.clone(), "tokeninfo");
-                // Shhhhhhh don't tell anyone
-                thread::sleep(Duration::from_millis(500));
+        spawn_job(Priority::Interactive, move || {
+            *loading_state.lock().unwrap() = LoadingState::Loading;
+            let result = utils::request::<Gw2ApiKey>(check_api_key.clone(), "tokeninfo");
+            // Shhhhhhh don't tell anyone
+            thread::sleep(Duration::from_millis(500));
 
-                match result {
-                    Err(_) => {
-                        *loading_state.lock().unwrap() = LoadingState::Error("Invalid Api Key!");
-                    }
-                    Ok(api_key) => {
-                        if api_key.permissions.contains(&Gw2Permission::Inventories)
-                            && api_key.permissions.contains(&Gw2Permission::Account)
-                        {
-                            *loading_state.lock().unwrap() =
-                                LoadingState::Success(check_api_key.clone())
-                        } else {
-                            *loading_state.lock().unwrap() =
-                                LoadingState::Error("Invalid permissions!");
-                        }
+            match result {
+                Err(_) => {
+                    *loading_state.lock().unwrap() = LoadingState::Error("Invalid Api Key!");
+                }
+                Ok(api_key) => {
+                    if api_key.permissions.contains(&Gw2Permission::Inventories)
+                        && api_key.permissions.contains(&Gw2Permission::Account)
+                    {
+                        *loading_state.lock().unwrap() =
+                            LoadingState::Success(check_api_key.clone())
+                    } else {
+                        *loading_state.lock().unwrap() =
+                            LoadingState::Error("Invalid permissions!");
                     }
                 }
-            }));
-        }
+            }
+        });
     }
 
     pub fn update(&mut self) {
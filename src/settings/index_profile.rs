@@ -0,0 +1,113 @@
+use nexus::imgui::{Slider, Ui};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use crate::spawn_job;
+use crate::tantivy::{compact_index, format_size};
+use crate::workers::Priority;
+
+/// When segments of the index are merged
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MergePolicyKind {
+    /// Tantivy merges segments in the background after commits
+    #[default]
+    Background,
+    /// Segments are only merged by "Compact now"
+    Manual,
+}
+
+impl MergePolicyKind {
+    pub const ALL: [MergePolicyKind; 2] = [MergePolicyKind::Background, MergePolicyKind::Manual];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MergePolicyKind::Background => "Merge in background",
+            MergePolicyKind::Manual => "Only when compacting",
+        }
+    }
+}
+
+/// Resources the search index may use inside the game process
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IndexProfile {
+    pub writer_heap_mb: u32,
+    pub merge_policy: MergePolicyKind,
+    /// Keeps the whole index in memory, it is rebuilt from the item records on every load
+    pub in_memory: bool,
+    #[serde(skip)]
+    compact_result: Arc<Mutex<Option<String>>>,
+}
+
+/// Tantivy needs at least 15 MB per indexing thread
+pub const MIN_WRITER_HEAP_MB: u32 = 15;
+pub const MAX_WRITER_HEAP_MB: u32 = 256;
+
+impl Default for IndexProfile {
+    fn default() -> Self {
+        Self {
+            writer_heap_mb: 50,
+            merge_policy: MergePolicyKind::Background,
+            in_memory: false,
+            compact_result: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl IndexProfile {
+    pub fn writer_heap_bytes(&self) -> usize {
+        self.writer_heap_mb
+            .clamp(MIN_WRITER_HEAP_MB, MAX_WRITER_HEAP_MB) as usize
+            * 1024
+            * 1024
+    }
+
+    pub fn render(&mut self, ui: &Ui) {
+        ui.text("Search Index");
+        Slider::new("Writer Memory (MB)", MIN_WRITER_HEAP_MB, MAX_WRITER_HEAP_MB)
+            .build(ui, &mut self.writer_heap_mb);
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Memory used while indexing, a few thousand items need very little");
+        }
+
+        let mut policy_index = MergePolicyKind::ALL
+            .iter()
+            .position(|p| *p == self.merge_policy)
+            .unwrap_or(0);
+        if ui.combo(
+            "Segment Merging",
+            &mut policy_index,
+            &MergePolicyKind::ALL,
+            |policy| Cow::from(policy.label()),
+        ) {
+            self.merge_policy = MergePolicyKind::ALL[policy_index];
+        }
+
+        ui.checkbox("Keep index in memory only", &mut self.in_memory);
+        if ui.is_item_hovered() {
+            ui.tooltip_text(
+                "Nothing is written to disk, good for small accounts.\n\
+                The index is rebuilt from the stored items on every load",
+            );
+        }
+        ui.text_disabled("Changes apply after reloading the addon");
+
+        if ui.button("Compact now") {
+            let compact_result = self.compact_result.clone();
+            *compact_result.lock().unwrap() = Some("Compacting...".to_string());
+            spawn_job(Priority::Background, move || {
+                let result = match compact_index() {
+                    Ok(Some(size)) => format!("Compacted, index uses {}", format_size(size)),
+                    Ok(None) => "Compacted, index is kept in memory".to_string(),
+                    Err(e) => format!("Compacting failed: {e}"),
+                };
+                *compact_result.lock().unwrap() = Some(result);
+            });
+        }
+        if let Some(result) = self.compact_result.lock().unwrap().as_ref() {
+            ui.same_line();
+            ui.text(result);
+        }
+    }
+}
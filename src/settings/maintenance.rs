@@ -0,0 +1,219 @@
+use log::{error, info};
+use nexus::imgui::Ui;
+use std::fs::{read_dir, remove_dir_all, remove_file};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::context::ctx;
+use crate::index::item_loader::{fetch_all_items, index_records, is_refreshing, without_refresh};
+use crate::records::RecordStore;
+use crate::spawn_job;
+use crate::tantivy::{format_size, opened_index, with_index_closed};
+use crate::utils::sub_path;
+use crate::workers::Priority;
+
+/// Actions of the maintenance section, all of them have to be confirmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceAction {
+    FullRefresh,
+    RebuildIndex,
+    ClearCaches,
+    ClearRecords,
+    WipeData,
+}
+
+impl MaintenanceAction {
+    const ALL: [MaintenanceAction; 5] = [
+        MaintenanceAction::FullRefresh,
+        MaintenanceAction::RebuildIndex,
+        MaintenanceAction::ClearCaches,
+        MaintenanceAction::ClearRecords,
+        MaintenanceAction::WipeData,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            MaintenanceAction::FullRefresh => "Full Refresh",
+            MaintenanceAction::RebuildIndex => "Rebuild Index",
+            MaintenanceAction::ClearCaches => "Clear Caches",
+            MaintenanceAction::ClearRecords => "Clear Stored Items",
+            MaintenanceAction::WipeData => "Wipe all Data",
+        }
+    }
+
+    /// Changes the stored data a refresh works on, so it can't run during one
+    fn conflicts_with_refresh(&self) -> bool {
+        !matches!(
+            self,
+            MaintenanceAction::FullRefresh | MaintenanceAction::ClearCaches
+        )
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            MaintenanceAction::FullRefresh => "Fetches all items from the API again",
+            MaintenanceAction::RebuildIndex => {
+                "Indexes the stored items again, without asking the API"
+            }
+            MaintenanceAction::ClearCaches => {
+                "Forgets item details and icons, they are fetched again when needed.\n\
+                Nexus keeps the old icons until the game restarts"
+            }
+            MaintenanceAction::ClearRecords => "Forgets all stored items and fetches them again",
+            MaintenanceAction::WipeData => {
+                "Deletes the index, stored items, synonyms and settings including the API key"
+            }
+        }
+    }
+}
+
+/// Statistics and maintenance actions for the index and the stored data
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    confirm: Option<MaintenanceAction>,
+    /// Outcome of the last wipe, it runs in the background
+    wipe_result: Arc<Mutex<Option<String>>>,
+    wiped: Arc<AtomicBool>,
+}
+
+impl Maintenance {
+    /// Returns [MaintenanceAction::WipeData] once all data was wiped, the settings have to be reset by the caller
+    pub fn render(
+        &mut self,
+        ui: &Ui,
+        last_refresh_duration: Option<Duration>,
+    ) -> Option<MaintenanceAction> {
+        ui.text("Maintenance");
+        if self.wiped.swap(false, Ordering::SeqCst) {
+            return Some(MaintenanceAction::WipeData);
+        }
+
+        // Opening the index needs the settings, which are locked while rendering them
+        match opened_index() {
+            Some(index) => {
+                let searcher = index.searcher();
+                ui.text(format!(
+                    "{} items in {} segments, {}",
+                    searcher.num_docs(),
+                    searcher.segment_readers().len(),
+                    index
+                        .size()
+                        .map(format_size)
+                        .unwrap_or("kept in memory".to_string())
+                ));
+            }
+            None => ui.text("Index not opened yet"),
+        }
+        match last_refresh_duration {
+            Some(duration) => ui.text(format!("Last refresh took {:.1}s", duration.as_secs_f32())),
+            None => ui.text("No refresh finished yet"),
+        }
+
+        let jobs = ctx().workers.stats();
+        ui.text(format!(
+            "Jobs: {} running, {} queued, {} finished, {} failed",
+            jobs.running, jobs.queued, jobs.finished, jobs.panicked
+        ));
+
+        if let Some(action) = self.confirm {
+            ui.text_colored(
+                [1.0, 0.8, 0.2, 1.0],
+                format!("{}?\n{}", action.label(), action.description()),
+            );
+            if action.conflicts_with_refresh() && is_refreshing() {
+                ui.text_disabled("Waiting for the running refresh");
+            } else if ui.button("Confirm") {
+                self.confirm = None;
+                self.run(action);
+            }
+            ui.same_line();
+            if ui.button("Cancel") {
+                self.confirm = None;
+            }
+        } else {
+            for (i, action) in MaintenanceAction::ALL.iter().enumerate() {
+                if i > 0 {
+                    ui.same_line();
+                }
+                if ui.button(action.label()) {
+                    self.confirm = Some(*action);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(action.description());
+                }
+            }
+        }
+        if let Some(result) = self.wipe_result.lock().unwrap().as_ref() {
+            ui.text(result);
+        }
+
+        None
+    }
+
+    fn run(&self, action: MaintenanceAction) {
+        info!("Running maintenance action {action:?}");
+        match action {
+            MaintenanceAction::FullRefresh => spawn_job(Priority::Background, fetch_all_items),
+            MaintenanceAction::RebuildIndex => spawn_job(Priority::Background, || {
+                without_refresh(index_records);
+            }),
+            MaintenanceAction::ClearCaches => {
+                let ctx = ctx();
+                ctx.item_cache.write().unwrap().clear();
+                ctx.icon_generation.fetch_add(1, Ordering::SeqCst);
+            }
+            MaintenanceAction::ClearRecords => spawn_job(Priority::Background, || {
+                let cleared = without_refresh(|| {
+                    RecordStore::clear();
+                    Self::remove("records");
+                });
+                if cleared.is_some() {
+                    fetch_all_items();
+                }
+            }),
+            MaintenanceAction::WipeData => {
+                let wipe_result = self.wipe_result.clone();
+                let wiped = self.wiped.clone();
+                *wipe_result.lock().unwrap() = Some("Wiping...".to_string());
+                spawn_job(Priority::Background, move || {
+                    // A refresh would recreate the index and records while they are deleted.
+                    // Open files can't be deleted, so the index stays closed meanwhile
+                    let result = without_refresh(|| {
+                        with_index_closed(|| {
+                            RecordStore::clear();
+                            Self::remove("");
+                        })
+                    });
+                    let message = match result {
+                        None => "A refresh started, wipe again once it is done".to_string(),
+                        Some(Err(e)) => format!("Wiping failed: {e}"),
+                        Some(Ok(_)) => {
+                            wiped.store(true, Ordering::SeqCst);
+                            "Wiped all data".to_string()
+                        }
+                    };
+                    *wipe_result.lock().unwrap() = Some(message);
+                });
+            }
+        }
+    }
+
+    /// Removes everything below the given addon sub path
+    fn remove(sub_dir: &str) {
+        let Ok(entries) = read_dir(unsafe { sub_path(sub_dir) }) else {
+            return;
+        };
+
+        for path in entries.flatten().map(|e| e.path()) {
+            let result = if path.is_dir() {
+                remove_dir_all(&path)
+            } else {
+                remove_file(&path)
+            };
+            if let Err(e) = result {
+                error!("Failed to remove {}: {e}", path.display());
+            }
+        }
+    }
+}
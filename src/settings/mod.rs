@@ -1,2 +1,4 @@
 pub mod api_key_loader;
+pub mod index_profile;
+pub mod maintenance;
 pub mod settings;
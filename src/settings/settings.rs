@@ -4,10 +4,17 @@ use serde::{Deserialize, Serialize};
 use std::cell::{Cell, RefCell};
 use std::fs::{create_dir_all, File};
 use std::path::Path;
-use std::sync::OnceLock;
+use std::time::Duration;
 
+use crate::context::ctx;
 use crate::entities::LoadingState;
 use crate::settings::api_key_loader::ApiKeyLoader;
+use crate::settings::index_profile::IndexProfile;
+use crate::settings::maintenance::Maintenance;
+
+/// The API caches account data for about 5 minutes, refreshing more often finds nothing new
+pub const MIN_AUTO_REFRESH_MINUTES: u32 = 5;
+pub const MAX_AUTO_REFRESH_MINUTES: u32 = 120;
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Settings {
@@ -15,13 +22,22 @@ pub struct Settings {
     pub item_load_limit: i32,
     pub color_items: bool,
     pub last_update: Option<DateTime<Local>>,
+    /// Id of the account the item records were last loaded for
+    pub account: Option<String>,
+    #[serde(default)]
+    pub index_profile: IndexProfile,
+    pub last_refresh_duration: Option<Duration>,
+    /// Minutes between automatic refreshes, 0 disables them
+    #[serde(default)]
+    pub auto_refresh_minutes: u32,
     #[serde(skip)]
     temp_api_key: String,
     #[serde(skip)]
     loader: ApiKeyLoader,
+    #[serde(skip)]
+    maintenance: Maintenance,
 }
 
-static mut SETTINGS: OnceLock<Settings> = OnceLock::new();
 impl Settings {
     pub fn new() -> Self {
         Self {
@@ -29,28 +45,31 @@ impl Settings {
             item_load_limit: 10,
             color_items: true,
             last_update: None,
+            account: None,
+            index_profile: IndexProfile::default(),
+            last_refresh_duration: None,
+            auto_refresh_minutes: 0,
             temp_api_key: "".to_string(),
             loader: ApiKeyLoader::new(),
+            maintenance: Maintenance::default(),
         }
     }
 
-    pub fn update_last_update(&mut self) {
+    pub fn update_last_update(&mut self, refresh_duration: Duration) {
         self.last_update = Some(Local::now());
+        self.last_refresh_duration = Some(refresh_duration);
     }
 
-    pub fn take() -> Option<Self> {
-        unsafe { SETTINGS.take() }
-    }
-
-    pub fn get_mut() -> &'static mut Self {
-        unsafe {
-            if let Some(settings) = SETTINGS.get_mut() {
-                return settings;
-            }
-
-            let _ = SETTINGS.set(Self::new());
-            Self::get_mut()
+    /// Time between automatic refreshes, if enabled
+    pub fn auto_refresh_interval(&self) -> Option<Duration> {
+        if self.auto_refresh_minutes == 0 {
+            return None;
         }
+
+        let minutes = self
+            .auto_refresh_minutes
+            .clamp(MIN_AUTO_REFRESH_MINUTES, MAX_AUTO_REFRESH_MINUTES);
+        Some(Duration::from_secs(minutes as u64 * 60))
     }
 
     pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
@@ -78,10 +97,6 @@ impl Settings {
         Ok(serde_json::to_writer_pretty(&mut file, self)?)
     }
 
-    pub fn get() -> &'static Self {
-        unsafe { SETTINGS.get_or_init(|| Self::new()) }
-    }
-
     pub fn render(&mut self, ui: &Ui) {
         thread_local! {
             static EDIT: Cell<bool> = Cell::new(false);
@@ -132,7 +147,39 @@ impl Settings {
 
         Slider::new("Item Load Limit", 1, 30).build(ui, &mut self.item_load_limit);
         ui.checkbox("Color items by Rarity", &mut self.color_items);
+        if Slider::new("Auto Refresh (minutes)", 0, MAX_AUTO_REFRESH_MINUTES)
+            .build(ui, &mut self.auto_refresh_minutes)
+            && self.auto_refresh_minutes > 0
+        {
+            self.auto_refresh_minutes = self.auto_refresh_minutes.max(MIN_AUTO_REFRESH_MINUTES);
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text(format!(
+                "0 disables automatic refreshes.\n\
+                The API updates account data only every {MIN_AUTO_REFRESH_MINUTES} minutes"
+            ));
+        }
+
+        ui.separator();
+        self.index_profile.render(ui);
+
+        ui.separator();
+        let wiped = self
+            .maintenance
+            .render(ui, self.last_refresh_duration)
+            .is_some();
+
+        if ui.button("Open Diagnostics") {
+            ctx().diagnostics.lock().unwrap().show = true;
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Recent requests, refreshes and searches, for bug reports");
+        }
 
         EDIT.set(edit);
+        if wiped {
+            *self = Self::new();
+            EDIT.set(false);
+        }
     }
 }
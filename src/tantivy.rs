@@ -1,114 +1,376 @@
+use crate::context::ctx;
+use crate::diagnostics;
+use crate::index::item_loader::index_records;
+use crate::settings::index_profile::{IndexProfile, MergePolicyKind};
+use crate::spawn_job;
 use crate::utils::sub_path;
-use std::fs::{create_dir_all, remove_file};
-use std::sync::OnceLock;
+use crate::workers::Priority;
+use anyhow::bail;
+use log::error;
+use std::fs::{
+    create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, rename, write,
+};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tantivy::indexer::{LogMergePolicy, NoMergePolicy};
 use tantivy::schema::{
-    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, INDEXED, STORED, TEXT,
+    FacetOptions, Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED,
+    STORED, STRING, TEXT,
 };
 use tantivy::tokenizer::NgramTokenizer;
 use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, TantivyDocument};
 
-static mut INDEX: OnceLock<Index> = OnceLock::new();
-static mut READER: OnceLock<IndexReader> = OnceLock::new();
-static mut WRITER: OnceLock<IndexWriter> = OnceLock::new();
+/// Bump whenever [schema] or the registered tokenizers change, older indexes are rebuilt
+const SCHEMA_VERSION: u32 = 3;
+const SCHEMA_VERSION_FILE: &str = "schema_version";
 
-pub fn tantivy_index() -> &'static Index {
-    unsafe {
-        if let Some(index) = INDEX.get() {
-            return index;
-        }
+/// Max length of prefixes indexed in the `name_prefix` field
+pub const EDGE_NGRAM_MAX: usize = 32;
+
+/// Separates the lowercase lookup key from the displayed name in the `suggest` field
+pub const SUGGEST_SEPARATOR: char = '\0';
+
+/// The opened index with its reader and writer, stored in the [crate::context::AppContext]
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    in_memory: bool,
+}
 
-        let index_dir = sub_path("index");
-        if !index_dir.clone().exists() {
-            create_dir_all(index_dir.clone()).expect("dir to be created");
+impl SearchIndex {
+    /// Opens the index of the profile. Returns true as well if it had to be recreated empty
+    fn open(profile: &IndexProfile) -> (Self, bool) {
+        if !profile.in_memory {
+            let opened = open_or_recreate(&unsafe { sub_path("index") })
+                .and_then(|(index, recreated)| Ok((Self::new(index, profile, false)?, recreated)));
+            match opened {
+                Ok(opened) => return opened,
+                Err(e) => {
+                    error!("Search index can't be opened, keeping it in memory: {e}");
+                    *ctx().index_notice.lock().unwrap() = Some(format!(
+                        "The search index can't be written to disk, it is kept in memory until the next load.\n{e}"
+                    ));
+                }
+            }
         }
 
-        let index = match Index::create_in_dir(index_dir.clone(), schema()) {
-            Ok(index) => index,
-            Err(_) => Index::open_in_dir(index_dir.clone()).unwrap(),
-        };
+        let index = Self::new(Index::create_in_ram(schema()), profile, true)
+            .expect("in memory index to be created");
+        (index, !profile.in_memory)
+    }
 
+    fn new(index: Index, profile: &IndexProfile, in_memory: bool) -> anyhow::Result<Self> {
+        // Infix matches
         index
             .tokenizers()
-            .register("ngram", NgramTokenizer::new(3, 10, false).unwrap());
+            .register("ngram", NgramTokenizer::all_ngrams(2, 3)?);
+        // Prefix matches, also catches 1-2 char queries
+        index.tokenizers().register(
+            "edge_ngram",
+            NgramTokenizer::prefix_only(1, EDGE_NGRAM_MAX)?,
+        );
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+
+        // Fails if the directory is locked by another writer
+        let writer: IndexWriter = index.writer(profile.writer_heap_bytes())?;
+        match profile.merge_policy {
+            MergePolicyKind::Background => {
+                writer.set_merge_policy(Box::<LogMergePolicy>::default())
+            }
+            MergePolicyKind::Manual => writer.set_merge_policy(Box::new(NoMergePolicy)),
+        }
+
+        Ok(Self {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            in_memory,
+        })
+    }
 
-        let _ = INDEX.set(index);
-        tantivy_index()
+    pub fn searcher(&self) -> Searcher {
+        self.reader.searcher()
+    }
+
+    /// Size of all index files, [None] if the index is kept in memory
+    pub fn size(&self) -> Option<u64> {
+        if self.in_memory {
+            return None;
+        }
+
+        let entries = unsafe { read_dir(sub_path("index")) }.ok()?;
+        Some(
+            entries
+                .flatten()
+                .filter_map(|e| e.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum(),
+        )
     }
 }
 
-pub fn cleanup_tantivy() {
-    unsafe {
-        if let Some(writer) = WRITER.take() {
-            let _ = writer.wait_merging_threads();
+/// The search index, opened on first use
+fn search_index() -> Arc<SearchIndex> {
+    let ctx = ctx();
+    if let Some(index) = ctx.index.read().unwrap().clone() {
+        return index;
+    }
+
+    // Read before locking the index, the settings window holds the settings lock while rendering
+    let profile = ctx.settings.read().unwrap().index_profile.clone();
+    let mut index = ctx.index.write().unwrap();
+    if let Some(index) = index.as_ref() {
+        return index.clone();
+    }
+
+    let (opened, recreated) = SearchIndex::open(&profile);
+    let opened = Arc::new(opened);
+    *index = Some(opened.clone());
+    if recreated {
+        // Make the stored items searchable again without waiting for the API
+        spawn_job(Priority::Background, index_records);
+    }
+
+    opened
+}
+
+/// The search index if it is already open, never opens it or waits while it is being closed
+pub fn opened_index() -> Option<Arc<SearchIndex>> {
+    ctx().index.try_read().ok()?.clone()
+}
+
+pub fn tantivy_index() -> Index {
+    search_index().index.clone()
+}
+
+/// Opens the index on disk, a broken or outdated one is moved aside and replaced by an empty one.
+/// Returns true as well if it was replaced
+fn open_or_recreate(index_dir: &Path) -> anyhow::Result<(Index, bool)> {
+    match open_index(index_dir) {
+        Ok(index) => Ok((index, false)),
+        Err(e) => {
+            // Don't crash the game, start over
+            error!("Search index can't be used: {e}");
+            let backup = index_dir.with_extension("broken");
+            let _ = remove_dir_all(&backup);
+            let notice = match rename(index_dir, &backup) {
+                Ok(_) => format!(
+                    "The search index was outdated or damaged and is being rebuilt.\n\
+                    The old one was moved to {}",
+                    backup.display()
+                ),
+                Err(_) => {
+                    let _ = remove_dir_all(index_dir);
+                    "The search index was outdated or damaged and is being rebuilt.".to_string()
+                }
+            };
+            *ctx().index_notice.lock().unwrap() = Some(notice);
+
+            Ok((create_index(index_dir)?, true))
         }
+    }
+}
+
+/// Opens the existing index, failing if it has another schema version or damaged files
+fn open_index(index_dir: &Path) -> anyhow::Result<Index> {
+    if !index_dir.join("meta.json").exists() {
+        return create_index(index_dir);
+    }
 
-        let _ = READER.take();
-        let _ = INDEX.take();
+    let version = read_to_string(index_dir.join(SCHEMA_VERSION_FILE))
+        .ok()
+        .and_then(|v| v.trim().parse::<u32>().ok());
+    if version != Some(SCHEMA_VERSION) {
+        bail!("Index has schema version {version:?}, expected {SCHEMA_VERSION}");
+    }
+
+    let index = Index::open_in_dir(index_dir)?;
+    if index.schema() != schema() {
+        bail!("Index schema differs from schema version {SCHEMA_VERSION}");
+    }
+
+    let corrupted = index.validate_checksum()?;
+    if !corrupted.is_empty() {
+        bail!("Index files are corrupted: {corrupted:?}");
+    }
 
+    Ok(index)
+}
+
+fn create_index(index_dir: &Path) -> anyhow::Result<Index> {
+    create_dir_all(index_dir)?;
+    let index = Index::create_in_dir(index_dir, schema())?;
+    write(
+        index_dir.join(SCHEMA_VERSION_FILE),
+        SCHEMA_VERSION.to_string(),
+    )?;
+
+    Ok(index)
+}
+
+/// Message for the user if the index had to be reset
+pub fn index_notice() -> Option<String> {
+    ctx().index_notice.lock().unwrap().clone()
+}
+
+pub fn dismiss_index_notice() {
+    *ctx().index_notice.lock().unwrap() = None;
+}
+
+/// Runs `f` with the index closed, e.g. to delete its files. Fails if a job still uses the index.
+/// Nothing can open it again until `f` is done, searches afterwards see the new index
+pub fn with_index_closed<R>(f: impl FnOnce() -> R) -> anyhow::Result<R> {
+    let ctx = ctx();
+    let mut opened = ctx.index.write().unwrap();
+    if let Some(index) = opened.take() {
+        match Arc::try_unwrap(index) {
+            Ok(index) => index.writer.into_inner().unwrap().wait_merging_threads()?,
+            Err(index) => {
+                *opened = Some(index);
+                bail!("The index is still in use, try again shortly");
+            }
+        }
+    }
+
+    let result = f();
+    drop(opened);
+    ctx.index_generation.fetch_add(1, Ordering::SeqCst);
+
+    Ok(result)
+}
+
+/// Closes the index, it is opened again on the next use
+pub fn cleanup_tantivy() {
+    let Some(index) = ctx().index.write().unwrap().take() else {
+        return;
+    };
+
+    // Searches still running keep their own reference, the writer is released once they are done
+    if let Ok(index) = Arc::try_unwrap(index) {
+        let _ = index.writer.into_inner().unwrap().wait_merging_threads();
+    }
+
+    unsafe {
         let _ = remove_file(sub_path("index").join(".tantivy-writer.lock"));
         let _ = remove_file(sub_path("index").join(".tantivy-meta.lock"));
     }
 }
 
-pub fn add_documents<T>(iter: T)
+/// Replaces all documents, searches switch to the new generation once everything is committed
+pub fn add_documents<T>(iter: T) -> anyhow::Result<()>
+where
+    T: Iterator<Item = TantivyDocument>,
+{
+    let index = search_index();
+    let mut writer = index.writer.lock().unwrap();
+    let started = Instant::now();
+    let documents = match replace_all(&mut writer, iter) {
+        Ok(documents) => documents,
+        Err(e) => {
+            // Keep the last generation instead of a half written one
+            let _ = writer.rollback();
+            diagnostics::index_commit(0, started.elapsed(), Some(e.to_string()));
+            return Err(e.into());
+        }
+    };
+    diagnostics::index_commit(documents, started.elapsed(), None);
+
+    index.reader.reload()?;
+    ctx().index_generation.fetch_add(1, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Returns the amount of added documents
+fn replace_all<T>(writer: &mut IndexWriter, iter: T) -> tantivy::Result<usize>
 where
     T: Iterator<Item = TantivyDocument>,
 {
-    let writer = writer();
-    let _ = writer.delete_all_documents();
-    iter.for_each(|d| {
-        let _ = writer.add_document(d);
-    });
+    writer.delete_all_documents()?;
+    let mut documents = 0;
+    for doc in iter {
+        writer.add_document(doc)?;
+        documents += 1;
+    }
+    writer.commit()?;
 
-    let _ = writer.prepare_commit();
-    let _ = writer.commit();
+    Ok(documents)
 }
 
-pub fn index_searcher() -> Searcher {
-    reader().searcher()
+/// Increased whenever a refresh was committed and is visible to searches
+pub fn index_generation() -> u64 {
+    ctx().index_generation.load(Ordering::SeqCst)
 }
 
-fn reader() -> &'static mut IndexReader {
-    unsafe {
-        if let Some(reader) = READER.get_mut() {
-            return reader;
-        }
+/// Merges all segments into one, returns the size on disk afterwards
+pub fn compact_index() -> anyhow::Result<Option<u64>> {
+    let index = search_index();
+    let mut writer = index.writer.lock().unwrap();
+    let segments = index.index.searchable_segment_ids()?;
+    if segments.len() > 1 {
+        writer.merge(&segments).wait()?;
+    }
+    writer.garbage_collect_files().wait()?;
+    index.reader.reload()?;
 
-        let r = tantivy_index()
-            .reader_builder()
-            .reload_policy(ReloadPolicy::Manual)
-            .try_into()
-            .unwrap();
+    Ok(index.size())
+}
 
-        let _ = READER.set(r);
-        reader()
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{bytes} B"),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)),
     }
 }
 
-fn writer() -> &'static mut IndexWriter {
-    unsafe {
-        if let Some(writer) = WRITER.get_mut() {
-            return writer;
-        }
-
-        let _ = WRITER.set(tantivy_index().writer(1024 * 1024 * 256).unwrap());
-        writer()
-    }
+pub fn index_searcher() -> Searcher {
+    search_index().searcher()
 }
 
 fn schema() -> Schema {
     let mut schema_builder = Schema::builder();
-    let text_options = TextOptions::default()
-        .set_indexing_options(
-            TextFieldIndexing::default()
-                .set_tokenizer("ngram")
-                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
-        )
-        .set_stored();
+    let text_options = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer("ngram")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    );
+    // Positions are needed, all prefixes of one value share the same position
+    let prefix_options = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer("edge_ngram")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    );
+    let keyword_options = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer("raw")
+            .set_index_option(IndexRecordOption::Basic),
+    );
     let _ = schema_builder.add_u64_field("id", INDEXED | STORED);
     let _ = schema_builder.add_text_field("name", text_options.clone());
+    let _ = schema_builder.add_text_field("name_prefix", prefix_options);
+    let _ = schema_builder.add_text_field("name_keyword", keyword_options.clone());
+    let _ = schema_builder.add_text_field("name_words", TEXT);
+    let _ = schema_builder.add_text_field("suggest", keyword_options.clone());
     let _ = schema_builder.add_text_field("description", TEXT);
-    let _ = schema_builder.add_bytes_field("item", STORED);
+    let _ = schema_builder.add_text_field("rarity", STRING);
+    let _ = schema_builder.add_text_field("type", STRING);
+    let _ = schema_builder.add_text_field("location", STRING);
+    let _ = schema_builder.add_text_field("character", STRING);
+    // Fast fields are used for sorting
+    let _ = schema_builder.add_u64_field("count", INDEXED | FAST);
+    let _ = schema_builder.add_u64_field("name_rank", FAST);
+    let _ = schema_builder.add_u64_field("rarity_rank", FAST);
+    let _ = schema_builder.add_u64_field("value", FAST);
+    let _ = schema_builder.add_facet_field("facets", FacetOptions::default());
 
     schema_builder.build()
 }
@@ -117,8 +379,20 @@ fn schema() -> Schema {
 pub struct TantivySchema {
     pub id_field: Field,
     pub name_field: Field,
+    pub name_prefix_field: Field,
+    pub name_keyword_field: Field,
+    pub name_words_field: Field,
+    pub suggest_field: Field,
     pub descr_field: Field,
-    pub item_field: Field,
+    pub rarity_field: Field,
+    pub type_field: Field,
+    pub location_field: Field,
+    pub character_field: Field,
+    pub count_field: Field,
+    pub name_rank_field: Field,
+    pub rarity_rank_field: Field,
+    pub value_field: Field,
+    pub facet_field: Field,
 }
 
 impl From<Schema> for TantivySchema {
@@ -126,8 +400,20 @@ impl From<Schema> for TantivySchema {
         Self {
             id_field: value.get_field("id").unwrap(),
             name_field: value.get_field("name").unwrap(),
+            name_prefix_field: value.get_field("name_prefix").unwrap(),
+            name_keyword_field: value.get_field("name_keyword").unwrap(),
+            name_words_field: value.get_field("name_words").unwrap(),
+            suggest_field: value.get_field("suggest").unwrap(),
             descr_field: value.get_field("description").unwrap(),
-            item_field: value.get_field("item").unwrap(),
+            rarity_field: value.get_field("rarity").unwrap(),
+            type_field: value.get_field("type").unwrap(),
+            location_field: value.get_field("location").unwrap(),
+            character_field: value.get_field("character").unwrap(),
+            count_field: value.get_field("count").unwrap(),
+            name_rank_field: value.get_field("name_rank").unwrap(),
+            rarity_rank_field: value.get_field("rarity_rank").unwrap(),
+            value_field: value.get_field("value").unwrap(),
+            facet_field: value.get_field("facets").unwrap(),
         }
     }
 }
@@ -1,8 +1,10 @@
+use crate::constants::HIGHLIGHT_COLOR;
 use crate::fms_entities::player_item::{Location, PlayerItemSpecifics};
 use crate::{COPPER_ICON_ID, GOLD_ICON_ID, SILVER_ICON_ID};
 use nexus::imgui::{Image, StyleVar, Ui};
 use nexus::texture::get_texture;
 use std::collections::HashMap;
+use std::ops::Range;
 
 pub trait Renderable {
     fn title(&self) -> String;
@@ -53,6 +55,124 @@ fn draw_gold_unit(ui: &Ui, icon: &str, unit: usize) {
     ui.dummy([0.0, 20.0]);
 }
 
+/// Renders text on a single line, highlighted parts are drawn in [HIGHLIGHT_COLOR].
+/// Parts don't wrap, only use it for short texts like names
+pub fn render_highlighted(
+    ui: &Ui,
+    color: Option<[f32; 4]>,
+    text: &str,
+    highlights: &[Range<usize>],
+) {
+    let highlights = merge_ranges(highlights, text);
+    if highlights.is_empty() {
+        match color {
+            Some(color) => ui.text_colored(color, text),
+            None => ui.text(text),
+        }
+        return;
+    }
+
+    let mut pos = 0;
+    let mut first = true;
+    let mut render = |part: &str, color: Option<[f32; 4]>| {
+        if part.is_empty() {
+            return;
+        }
+        if !first {
+            ui.same_line_with_spacing(0.0, 0.0);
+        }
+        first = false;
+
+        match color {
+            Some(color) => ui.text_colored(color, part),
+            None => ui.text(part),
+        }
+    };
+
+    for range in highlights {
+        render(&text[pos..range.start], color);
+        render(&text[range.clone()], Some(HIGHLIGHT_COLOR));
+        pos = range.end;
+    }
+    render(&text[pos..], color);
+}
+
+/// Like [render_highlighted], but wraps at word boundaries once a line exceeds `wrap_width`
+pub fn render_highlighted_wrapped(
+    ui: &Ui,
+    text: &str,
+    highlights: &[Range<usize>],
+    wrap_width: f32,
+) {
+    let mut parts = vec![];
+    let mut pos = 0;
+    for range in merge_ranges(highlights, text) {
+        parts.push((&text[pos..range.start], None));
+        parts.push((&text[range.clone()], Some(HIGHLIGHT_COLOR)));
+        pos = range.end;
+    }
+    parts.push((&text[pos..], None));
+
+    let mut line_width = 0.0;
+    for (part, color) in parts {
+        for word in part.split_inclusive(' ') {
+            let width = ui.calc_text_size(word)[0];
+            if line_width > 0.0 && line_width + width > wrap_width {
+                line_width = 0.0;
+            } else if line_width > 0.0 {
+                ui.same_line_with_spacing(0.0, 0.0);
+            }
+            line_width += width;
+
+            match color {
+                Some(color) => ui.text_colored(color, word),
+                None => ui.text(word),
+            }
+        }
+    }
+}
+
+/// Sorts and merges overlapping ranges, drops the ones not fitting into the text
+fn merge_ranges(ranges: &[Range<usize>], text: &str) -> Vec<Range<usize>> {
+    let mut ranges = ranges
+        .iter()
+        .filter(|r| {
+            r.start < r.end && text.is_char_boundary(r.start) && text.is_char_boundary(r.end)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<Range<usize>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Removes the GW2 markup like `<c=@flavor>` or `<br>` from a description, line breaks become spaces
+pub fn strip_markup(input: &str) -> String {
+    let mut plain = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        plain.push_str(&rest[..start]);
+        if rest[start..start + end].starts_with("<br") {
+            plain.push(' ');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    plain.push_str(rest);
+
+    plain
+}
+
 pub fn render_description(ui: &Ui, input: &str) {
     let mut pos = 0;
     while pos < input.len() {
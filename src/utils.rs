@@ -1,23 +1,18 @@
+use anyhow::bail;
 use std::convert::identity;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use nexus::paths::get_addon_dir;
 use serde::de::DeserializeOwned;
 use ureq::Error;
 
-use crate::entities::Gw2Item;
-use crate::settings::settings::Settings;
+use crate::context::{cancelled, ctx};
+use crate::diagnostics;
+use crate::entities::{Gw2Item, Gw2Tp};
 
-pub trait Searcher<T> {
-    fn is_loading(&self) -> bool;
-
-    fn has_more(&self) -> bool;
-
-    fn search(&self, query: String, page: usize);
-
-    fn last_result(&self) -> T;
-}
+/// Max duration of a single http request. Jobs left behind on unload run until theirs ends, so keep it short
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn request<T: DeserializeOwned>(api_key: String, endpoint: &str) -> anyhow::Result<T> {
     let mut authorization = "Bearer ".to_string();
@@ -26,32 +21,66 @@ pub fn request<T: DeserializeOwned>(api_key: String, endpoint: &str) -> anyhow::
     let mut url = "https://api.guildwars2.com/v2/".to_string();
     url.push_str(endpoint);
 
-    match ureq::get(url.as_str())
-        .set("Authorization", &authorization)
-        .call()
-    {
-        Ok(response) => Ok(response.into_json::<T>()?),
-        Err(e) => match e {
-            Error::Status(code, _) => {
-                if code == 429 {
-                    std::thread::sleep(Duration::from_millis(500));
-                    request(api_key, endpoint)
-                } else {
-                    Err(e)?
-                }
+    let started = Instant::now();
+    let mut retries = 0;
+    loop {
+        if cancelled() {
+            diagnostics::request_cancelled(endpoint, started.elapsed(), retries);
+            bail!("Cancelled");
+        }
+
+        match ureq::get(url.as_str())
+            .set("Authorization", &authorization)
+            .timeout(REQUEST_TIMEOUT)
+            .call()
+        {
+            Ok(response) => {
+                diagnostics::request(
+                    endpoint,
+                    Some(response.status()),
+                    started.elapsed(),
+                    retries,
+                );
+                return Ok(response.into_json::<T>()?);
+            }
+            // Rate limited, try again shortly
+            Err(Error::Status(429, _)) => {
+                retries += 1;
+                std::thread::sleep(Duration::from_millis(500));
             }
-            Error::Transport(_) => Err(e)?,
-        },
+            Err(e) => {
+                let status = match &e {
+                    Error::Status(code, _) => Some(*code),
+                    Error::Transport(_) => None,
+                };
+                diagnostics::request(endpoint, status, started.elapsed(), retries);
+                Err(e)?
+            }
+        }
     }
 }
 
 pub fn auth_request<T: DeserializeOwned>(endpoint: &str) -> anyhow::Result<T> {
-    let settings = Settings::get();
-    request(settings.api_key.clone(), endpoint)
+    // Don't hold the lock during the request, the options window needs it every frame
+    let api_key = ctx().settings.read().unwrap().api_key.clone();
+    request(api_key, endpoint)
 }
 
+/// Details of the given items, only the ones not in the item cache are requested
 pub fn fetch_items(ids: Vec<usize>) -> Vec<Gw2Item> {
-    ids.chunks(200)
+    let ctx = ctx();
+    let mut items = vec![];
+    let mut missing = vec![];
+    for id in ids {
+        match ctx.item_cache.read().unwrap().get(&id) {
+            Some(item) => items.push(item.clone()),
+            None => missing.push(id),
+        }
+    }
+
+    let fetched = missing
+        .chunks(200)
+        .take_while(|_| !cancelled())
         .map(|ids| {
             let id_str = ids
                 .iter()
@@ -65,9 +94,53 @@ pub fn fetch_items(ids: Vec<usize>) -> Vec<Gw2Item> {
         })
         .filter_map(|i| i.ok())
         .flat_map(identity)
+        .collect::<Vec<_>>();
+
+    ctx.item_cache
+        .write()
+        .unwrap()
+        .extend(fetched.iter().map(|item| (item.id, item.clone())));
+    items.extend(fetched);
+
+    items
+}
+
+pub fn fetch_prices(ids: Vec<usize>) -> Vec<Gw2Tp> {
+    ids.chunks(200)
+        .take_while(|_| !cancelled())
+        .map(|ids| {
+            let id_str = ids
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let url = format!("commerce/prices?ids={id_str}");
+
+            auth_request::<Vec<Gw2Tp>>(url.as_str())
+        })
+        .filter_map(|i| i.ok())
+        .flat_map(identity)
         .collect()
 }
 
+/// Edit distance between two strings, counted in chars
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
 pub unsafe fn sub_path(sub_dir: &str) -> PathBuf {
     get_addon_dir("find-my-sht")
         .expect("addon dir to exist")
@@ -0,0 +1,214 @@
+use log::error;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Amount of worker threads, background jobs never occupy all of them
+const WORKERS: usize = 4;
+
+/// Jobs with a higher priority are started first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Refreshes and maintenance
+    Background,
+    /// Follow up work of the UI, e.g. prices and wiki lookups
+    Normal,
+    /// Searches and everything else the user is waiting for
+    Interactive,
+}
+
+struct Job {
+    priority: Priority,
+    /// Keeps jobs of the same priority in order
+    seq: usize,
+    task: Box<dyn FnOnce() + Send>,
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Set on unload, long running jobs check it and stop early
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, AtomicOrdering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(AtomicOrdering::SeqCst)
+    }
+}
+
+/// Job counts of the pool
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JobStats {
+    pub queued: usize,
+    pub running: usize,
+    pub finished: usize,
+    pub panicked: usize,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<BinaryHeap<Job>>,
+    available: Condvar,
+    shutdown: AtomicBool,
+    seq: AtomicUsize,
+    running: AtomicUsize,
+    running_background: AtomicUsize,
+    finished: AtomicUsize,
+    panicked: AtomicUsize,
+}
+
+/// Fixed amount of threads working through prioritized jobs
+pub struct WorkerPool {
+    shared: Arc<Shared>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl WorkerPool {
+    pub fn new() -> Self {
+        let shared = Arc::new(Shared::default());
+        let workers = (0..WORKERS)
+            .map(|i| {
+                let shared = shared.clone();
+                std::thread::Builder::new()
+                    .name(format!("find-my-sht-worker-{i}"))
+                    .spawn(move || Self::work(shared))
+                    .expect("worker to be started")
+            })
+            .collect();
+
+        Self {
+            shared,
+            workers: Mutex::new(workers),
+        }
+    }
+
+    pub fn spawn<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.shared.shutdown.load(AtomicOrdering::SeqCst) {
+            return;
+        }
+
+        self.shared.queue.lock().unwrap().push(Job {
+            priority,
+            seq: self.shared.seq.fetch_add(1, AtomicOrdering::SeqCst),
+            task: Box::new(f),
+        });
+        self.shared.available.notify_one();
+    }
+
+    pub fn stats(&self) -> JobStats {
+        JobStats {
+            queued: self.shared.queue.lock().unwrap().len(),
+            running: self.shared.running.load(AtomicOrdering::SeqCst),
+            finished: self.shared.finished.load(AtomicOrdering::SeqCst),
+            panicked: self.shared.panicked.load(AtomicOrdering::SeqCst),
+        }
+    }
+
+    /// Drops all queued jobs and waits for the running ones up to the given timeout.
+    /// Returns false if some jobs are still running, their workers are detached
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        // Under the lock, so no worker misses the wakeup
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            self.shared.shutdown.store(true, AtomicOrdering::SeqCst);
+            queue.clear();
+        }
+        self.shared.available.notify_all();
+
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let started = Instant::now();
+        while workers.iter().any(|w| !w.is_finished()) && started.elapsed() < timeout {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut stopped = true;
+        for worker in workers {
+            if worker.is_finished() {
+                let _ = worker.join();
+            } else {
+                stopped = false;
+            }
+        }
+
+        stopped
+    }
+
+    fn work(shared: Arc<Shared>) {
+        while let Some(job) = Self::next(&shared) {
+            let background = job.priority == Priority::Background;
+            if catch_unwind(AssertUnwindSafe(job.task)).is_err() {
+                error!("Worker job panicked");
+                shared.panicked.fetch_add(1, AtomicOrdering::SeqCst);
+            }
+
+            shared.running.fetch_sub(1, AtomicOrdering::SeqCst);
+            if background {
+                // Under the lock, so a worker can't miss the wakeup between its check and its wait
+                let _queue = shared.queue.lock().unwrap();
+                shared
+                    .running_background
+                    .fetch_sub(1, AtomicOrdering::SeqCst);
+                // A background job might have waited for this slot
+                shared.available.notify_all();
+            }
+            shared.finished.fetch_add(1, AtomicOrdering::SeqCst);
+        }
+    }
+
+    /// Blocks until there is a job this worker may run, [None] on shutdown
+    fn next(shared: &Shared) -> Option<Job> {
+        let mut queue = shared.queue.lock().unwrap();
+        loop {
+            if shared.shutdown.load(AtomicOrdering::SeqCst) {
+                return None;
+            }
+
+            // The heap is ordered by priority, only background jobs are left if the top is one
+            let background = queue.peek().map(|job| job.priority == Priority::Background);
+            let slot_free = shared.running_background.load(AtomicOrdering::SeqCst) < WORKERS - 1;
+            if background == Some(false) || (background == Some(true) && slot_free) {
+                let job = queue.pop()?;
+                shared.running.fetch_add(1, AtomicOrdering::SeqCst);
+                if job.priority == Priority::Background {
+                    shared
+                        .running_background
+                        .fetch_add(1, AtomicOrdering::SeqCst);
+                }
+                return Some(job);
+            }
+
+            queue = shared.available.wait(queue).unwrap();
+        }
+    }
+}